@@ -0,0 +1,110 @@
+//! Measures the encryption and decryption throughput of counter_block, and compares encryption
+//! with the per-block pipeline Blocks used before it held a single contiguous buffer.
+//! The input is 16 MiB of data by default, set BROKEN_BENCH_BYTES to change it, and
+//! BROKEN_BENCH_1GIB to also measure a 1 GiB input (which takes minutes, and a few GiB of memory):
+//!   cargo bench --bench throughput
+//!   BROKEN_BENCH_BYTES=104857600 cargo bench --bench throughput
+//!   BROKEN_BENCH_1GIB=1 cargo bench --bench throughput
+use common::counter_block;
+use common::defaults;
+use common::feistel;
+use common::nonce::SeededNonce;
+use common::progress;
+use common::secret::Key;
+use rayon::prelude::*;
+use std::cmp::max;
+use std::env;
+use std::time::Instant;
+
+fn main() {
+    let len: usize = env::var("BROKEN_BENCH_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(16 << 20);
+    let mut lens = vec![len];
+    if env::var_os("BROKEN_BENCH_1GIB").is_some() {
+        lens.push(1 << 30);
+    }
+    let key = Key::from("super_secret123!@#");
+    for &len in &lens {
+        for &block_size in &[defaults::BLOCK_SIZE, 4096] {
+            bench(&key, len, block_size);
+        }
+    }
+}
+
+fn bench(key: &Key, len: usize, block_size: usize) {
+    let msg: Vec<u8> = (0..len).map(|x| x as u8).collect();
+
+    let start = Instant::now();
+    let blocks = counter_block::par_encrypt(
+        msg.clone(),
+        key,
+        block_size,
+        defaults::ROUNDS,
+        &SeededNonce::new(1),
+        &progress::ignore,
+    )
+    .unwrap();
+    let contiguous = report("par_encrypt", block_size, len, start);
+
+    let start = Instant::now();
+    let old = per_block_encrypt(&msg, key, &blocks.nonce, block_size);
+    let per_block = report("per-block", block_size, len, start);
+    assert!(old.iter().map(Vec::as_slice).eq(blocks.blocks()));
+    drop((old, msg));
+    println!(
+        "{:<12} block size {:>5}: {:>8.2}x",
+        "speedup",
+        block_size,
+        per_block / contiguous
+    );
+
+    let start = Instant::now();
+    let dec = counter_block::par_decrypt(blocks, key, 0, &progress::ignore).unwrap();
+    report("par_decrypt", block_size, len, start);
+    assert!(dec.iter().enumerate().all(|(i, x)| *x == i as u8));
+}
+
+/// Encrypts msg the way par_encrypt did when Blocks held a Vec for every block: the clear text is
+/// copied into a Vec per block, and every block allocates its own nonce counter, feistel network
+/// and keystream. The keystream is the one par_encrypt generates for nonce, so the results can be
+/// compared.
+fn per_block_encrypt(msg: &[u8], key: &Key, nonce: &[u8], block_size: usize) -> Vec<Vec<u8>> {
+    let width = max(feistel::DEFAULT_WIDTH, block_size + block_size % 2);
+    msg.par_chunks(block_size)
+        .enumerate()
+        .map(|(counter, chunk)| {
+            let mut nonce_counter = nonce.to_vec();
+            nonce_counter.extend_from_slice(&(counter as u64).to_le_bytes());
+            let cypher = feistel::encrypt(
+                nonce_counter,
+                key,
+                defaults::ROUNDS,
+                width,
+                feistel::DEFAULT_KEY_LEN,
+            )
+            .unwrap();
+            let mut block = chunk.to_vec();
+            block.resize(block_size, 0);
+            block
+                .iter_mut()
+                .zip(cypher.iter())
+                .for_each(|(x1, x2)| *x1 ^= *x2);
+            block
+        })
+        .collect()
+}
+
+/// Prints the time since start and the throughput of len bytes, and returns the time in seconds.
+fn report(name: &str, block_size: usize, len: usize, start: Instant) -> f64 {
+    let secs = start.elapsed().as_secs_f64();
+    println!(
+        "{:<12} block size {:>5}: {:>8.2}s {:>8.2} MiB/s",
+        name,
+        block_size,
+        secs,
+        len as f64 / secs / (1 << 20) as f64
+    );
+    secs
+}
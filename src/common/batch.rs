@@ -0,0 +1,360 @@
+#![warn(missing_debug_implementations, missing_docs)]
+use crate::cancel::CancelToken;
+use crate::counter_block;
+use crate::counter_block::Chunking;
+use crate::defaults;
+use crate::error::*;
+use crate::exec::{Execution, Strategy};
+use crate::nonce::NonceSource;
+use crate::progress::{self, FileProgress};
+use crate::secret::Key;
+use rayon::prelude::*;
+use std::cmp::{max, min};
+use std::fmt;
+use std::fs::metadata;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Limits on the resources used by encrypt_batch, how it reports its progress, and how it is
+/// cancelled.
+#[derive(Clone, Copy)]
+pub struct BatchOptions<'a> {
+    /// The maximal number of bytes held in memory by all the files being encrypted together.
+    pub memory_budget: usize,
+    /// execution: the thread pool the files are encrypted in (rayon's global pool if it has none),
+    /// and its strategy: whether files are encrypted one per thread (PerFile), one at a time with
+    /// their chunks split between the threads (PerBlock), or one at a time by a single thread.
+    pub execution: &'a Execution,
+    /// overwrite: whether existing encrypted files are replaced. Otherwise they are left as they
+    /// are, and their clear files fail.
+    pub overwrite: bool,
+    /// keep_going: whether the files left are still encrypted after one of them fails. Otherwise
+    /// no file is started after a failure, and the files that were not started have no outcome.
+    pub keep_going: bool,
+    /// progress: called with the path of a file as every chunk of its blocks is written.
+    pub progress: &'a FileProgress<'a>,
+    /// cancel: once it is cancelled, the files being encrypted stop at their next block and fail
+    /// with Cancelled (their encrypted files are removed), and no file is started.
+    pub cancel: Option<&'a CancelToken>,
+}
+
+impl fmt::Debug for BatchOptions<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BatchOptions")
+            .field("memory_budget", &self.memory_budget)
+            .field("execution", &self.execution)
+            .field("overwrite", &self.overwrite)
+            .field("keep_going", &self.keep_going)
+            .field("cancel", &self.cancel)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The execution of BatchOptions::default: Auto, in rayon's global pool.
+static DEFAULT_EXECUTION: Execution = Execution {
+    strategy: Strategy::Auto,
+    pool: None,
+};
+
+impl Default for BatchOptions<'_> {
+    fn default() -> Self {
+        BatchOptions {
+            memory_budget: defaults::BATCH_MEMORY,
+            execution: &DEFAULT_EXECUTION,
+            overwrite: false,
+            keep_going: true,
+            progress: &progress::ignore_files,
+            cancel: None,
+        }
+    }
+}
+
+/// The outcome of encrypting a single file of a batch.
+#[derive(Debug)]
+pub struct FileOutcome {
+    /// path: the clear file.
+    pub path: String,
+    /// enc_path: the encrypted file written for it.
+    pub enc_path: String,
+    /// result: whether the file was encrypted, or why it was not.
+    pub result: Result<(), EncryptErr>,
+}
+
+/// Encrypts every file in paths to the path returned by enc_path for it, and returns the outcome
+/// of every file, in the order of paths. A failure in one file does not stop the others, unless
+/// options.keep_going is false. Once options.cancel is cancelled, the files that were not started
+/// have no outcome, and the ones being encrypted fail with Cancelled, so an encrypted file is
+/// either complete or not there at all.
+/// Every file is written as soon as it is encrypted, and is never loaded into memory as a whole:
+/// files are encrypted in chunks on the threads of options.execution, and the chunks are sized so
+/// all of them together fit in options.memory_budget. If the budget can not hold a block for every
+/// thread, a pool of less threads is created for the batch; otherwise no threads are created. With
+/// the PerFile strategy every thread encrypts a file of its own; otherwise the files are encrypted
+/// one after the other.
+/// # Errors
+/// returns an error only if the worker threads can not be created.
+pub fn encrypt_batch(
+    paths: &[String],
+    enc_path: &(dyn Fn(&str) -> String + Sync),
+    key: &Key,
+    block_size: usize,
+    f_rounds: i32,
+    nonce_source: &dyn NonceSource,
+    options: &BatchOptions,
+) -> Result<Vec<FileOutcome>, EncryptErr> {
+    let (concurrency, chunk_blocks) = plan(options, block_size);
+    let limited;
+    let execution = if concurrency == options.execution.threads() {
+        options.execution
+    } else {
+        limited = Execution::with_threads(options.execution.strategy, concurrency)
+            .map_err(|e| EncryptErr::IoError(e.to_string()))?;
+        &limited
+    };
+
+    let total_len = paths
+        .iter()
+        .map(|p| metadata(p).map(|m| m.len()).unwrap_or(0))
+        .sum();
+    let strategy = execution.resolve(paths.len(), total_len);
+    let chunking = Chunking {
+        blocks: chunk_blocks,
+        parallel: strategy == Strategy::PerBlock,
+        overwrite: options.overwrite,
+        progress: options.progress,
+        cancel: options.cancel,
+    };
+    let failed = AtomicBool::new(false);
+    let encrypt = |p: &String| {
+        if (!options.keep_going && failed.load(Ordering::Relaxed))
+            || options.cancel.is_some_and(CancelToken::is_cancelled)
+        {
+            return None;
+        }
+        let enc_path = enc_path(p);
+        let result = counter_block::encrypt_file_chunked(
+            p,
+            &enc_path,
+            key,
+            block_size,
+            f_rounds,
+            nonce_source,
+            chunking,
+        );
+        if result.is_err() {
+            failed.store(true, Ordering::Relaxed);
+        }
+        Some(FileOutcome {
+            path: p.clone(),
+            enc_path,
+            result,
+        })
+    };
+
+    Ok(execution.install(|| match strategy {
+        Strategy::PerFile => paths.par_iter().filter_map(encrypt).collect(),
+        _ => paths.iter().filter_map(encrypt).collect(),
+    }))
+}
+
+/// Splits the memory budget between the threads of the execution: returns the number of files to
+/// encrypt at a time, and the number of blocks in every chunk.
+fn plan(options: &BatchOptions, block_size: usize) -> (usize, usize) {
+    let budget_blocks = max(1, options.memory_budget / block_size);
+    let concurrency = max(1, min(options.execution.threads(), budget_blocks));
+    (concurrency, budget_blocks / concurrency)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counter_block::Blocks;
+    use crate::nonce::OsNonce;
+    use crate::test_util::{key, temp_path};
+    use std::fs;
+    use std::path::PathBuf;
+
+    #[test]
+    fn plan_fits_budget() {
+        let execution = Execution::with_threads(Strategy::Auto, 4).unwrap();
+        let options = BatchOptions {
+            memory_budget: 1000,
+            execution: &execution,
+            overwrite: false,
+            keep_going: true,
+            progress: &progress::ignore_files,
+            cancel: None,
+        };
+        assert_eq!(plan(&options, 100), (4, 2));
+        assert_eq!(plan(&options, 300), (3, 1));
+        assert_eq!(plan(&options, 5000), (1, 1));
+    }
+
+    #[test]
+    fn runs_in_the_given_pool() {
+        use rayon::ThreadPoolBuilder;
+        use std::sync::{Arc, Mutex};
+        let paths: Vec<String> = ["a", "b", "c"]
+            .iter()
+            .map(|n| temp_path(&format!("pool_{}", n)))
+            .collect();
+        for p in paths.iter() {
+            fs::write(p, vec![7u8; 5000]).unwrap();
+        }
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(2)
+            .thread_name(|i| format!("caller_{}", i))
+            .build()
+            .unwrap();
+        let execution = Execution {
+            strategy: Strategy::PerFile,
+            pool: Some(Arc::new(pool)),
+        };
+        let threads = Mutex::new(Vec::new());
+        let options = BatchOptions {
+            execution: &execution,
+            progress: &|_, _, _| {
+                let name = std::thread::current().name().map(String::from);
+                threads.lock().unwrap().push(name);
+            },
+            ..BatchOptions::default()
+        };
+        let res = encrypt_batch(
+            &paths,
+            &|p| format!("{}_enc", p),
+            &key(),
+            100,
+            5,
+            &OsNonce,
+            &options,
+        )
+        .unwrap();
+        for p in paths.iter() {
+            fs::remove_file(p).unwrap();
+            fs::remove_file(format!("{}_enc", p)).unwrap();
+        }
+        assert!(res.iter().all(|o| o.result.is_ok()));
+        let threads = threads.into_inner().unwrap();
+        assert!(!threads.is_empty());
+        assert!(threads
+            .iter()
+            .all(|t| t.as_deref().is_some_and(|t| t.starts_with("caller_"))));
+    }
+
+    #[test]
+    fn batch_outcomes() {
+        for &strategy in [Strategy::PerFile, Strategy::PerBlock, Strategy::Sequential].iter() {
+            batch_outcomes_with(strategy);
+        }
+    }
+
+    fn batch_outcomes_with(strategy: Strategy) {
+        let name = |n: &str| temp_path(&format!("batch_{}", n));
+        let clear: Vec<u8> = (0..10_000).map(|x| (x % 251) as u8).collect();
+        let paths = vec![name("a"), name("missing"), name("b")];
+        fs::write(&paths[0], &clear).unwrap();
+        fs::write(&paths[2], &clear[..333]).unwrap();
+
+        let key = key();
+        let execution = Execution::with_threads(strategy, 2).unwrap();
+        let options = BatchOptions {
+            memory_budget: 700,
+            execution: &execution,
+            overwrite: false,
+            keep_going: true,
+            progress: &progress::ignore_files,
+            cancel: None,
+        };
+        let res = encrypt_batch(
+            &paths,
+            &|p| format!("{}_enc", p),
+            &key,
+            100,
+            5,
+            &OsNonce,
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(res.len(), 3);
+        assert!(res[1].result.is_err());
+        for (o, len) in [(&res[0], 10_000), (&res[2], 333)].iter() {
+            assert!(o.result.is_ok());
+            let dec = Blocks::from_enc_file(&o.enc_path)
+                .unwrap()
+                .into_clear(&key, 0)
+                .unwrap();
+            assert_eq!(dec[..*len], clear[..*len]);
+            fs::remove_file(&o.path).unwrap();
+            fs::remove_file(&o.enc_path).unwrap();
+        }
+    }
+
+    #[test]
+    fn stop_after_failure() {
+        let name = |n: &str| temp_path(&format!("stop_{}", n));
+        let paths = vec![name("missing"), name("a")];
+        fs::write(&paths[1], b"never encrypted").unwrap();
+        let options = BatchOptions {
+            execution: &Strategy::Sequential.into(),
+            keep_going: false,
+            ..BatchOptions::default()
+        };
+        let res = encrypt_batch(
+            &paths,
+            &|p| format!("{}_enc", p),
+            &key(),
+            100,
+            5,
+            &OsNonce,
+            &options,
+        )
+        .unwrap();
+        fs::remove_file(&paths[1]).unwrap();
+        // the file after the failure is not started
+        assert_eq!(res.len(), 1);
+        assert!(res[0].result.is_err());
+        assert!(fs::metadata(format!("{}_enc", paths[1])).is_err());
+    }
+
+    #[test]
+    fn cancel_mid_batch() {
+        let dir = PathBuf::from(temp_path("cancel"));
+        fs::create_dir_all(&dir).unwrap();
+        let paths: Vec<String> = ["a", "b"]
+            .iter()
+            .map(|n| dir.join(n).display().to_string())
+            .collect();
+        for p in paths.iter() {
+            fs::write(p, vec![7u8; 5000]).unwrap();
+        }
+        // cancelled as the first chunk of the first file is written
+        let cancel = CancelToken::new();
+        let options = BatchOptions {
+            memory_budget: 1000,
+            execution: &Strategy::Sequential.into(),
+            progress: &|_, _, _| cancel.cancel(),
+            cancel: Some(&cancel),
+            ..BatchOptions::default()
+        };
+        let res = encrypt_batch(
+            &paths,
+            &|p| format!("{}_enc", p),
+            &key(),
+            100,
+            5,
+            &OsNonce,
+            &options,
+        )
+        .unwrap();
+        assert_eq!(res.len(), 1);
+        assert!(matches!(res[0].result, Err(EncryptErr::Cancelled)));
+        // only the clear files are left, without partial or temporary encrypted files
+        let mut left: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        left.sort();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(left, vec!["a", "b"]);
+    }
+}
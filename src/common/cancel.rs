@@ -0,0 +1,101 @@
+#![warn(missing_debug_implementations, missing_docs)]
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+
+/// Tells long running operations (encrypt_batch, encrypt_stream, decrypt_stream and
+/// decrypt_file_to) to stop. They check it between chunks (or blocks), and once it is cancelled
+/// return a Cancelled error. encrypt_batch removes the files it did not finish; the others leave
+/// what they wrote to out for their caller to throw away (a file_mng::AtomicFile that is dropped
+/// without commit removes itself). Clones of a token share its state, so it can be cancelled from
+/// another thread, or by ctrl-c (see on_interrupt).
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    /// A token that is not cancelled yet.
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    /// Cancels the token. Operations that check it stop at their next chunk (or block).
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns true once the token was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+/// The token cancelled by the signal handler of on_interrupt.
+static INTERRUPT: OnceLock<CancelToken> = OnceLock::new();
+
+/// Returns a token that is cancelled when the process gets SIGINT (ctrl-c) or SIGTERM, instead of
+/// being killed by them in the middle of writing its files. Only the first signal is caught: a
+/// second one kills the process as usual, in case it does not get to stop on its own. Every call
+/// returns the same token. System calls the signal interrupts are not restarted, so a read
+/// blocked on stdin stops too (see counter_block::encrypt_stream). Off unix no signal is caught,
+/// and the token is never cancelled.
+/// # Errors
+/// returns an error if the signal handlers can not be installed.
+pub fn on_interrupt() -> io::Result<CancelToken> {
+    let token = INTERRUPT.get_or_init(CancelToken::new).clone();
+    #[cfg(unix)]
+    for &sig in [libc::SIGINT, libc::SIGTERM].iter() {
+        catch(sig, handle_interrupt, false)?;
+    }
+    Ok(token)
+}
+
+#[cfg(unix)]
+extern "C" fn handle_interrupt(sig: libc::c_int) {
+    // only atomics and async-signal-safe calls in here
+    if let Some(token) = INTERRUPT.get() {
+        token.cancel();
+    }
+    unsafe { libc::signal(sig, libc::SIG_DFL) };
+}
+
+/// Makes handler handle the signal sig, and returns the action it had before (for restore). With
+/// restart, system calls interrupted by the signal are restarted; otherwise they fail with an
+/// Interrupted error, so a blocking read can be stopped.
+#[cfg(unix)]
+pub(crate) fn catch(
+    sig: libc::c_int,
+    handler: extern "C" fn(libc::c_int),
+    restart: bool,
+) -> io::Result<libc::sigaction> {
+    let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+    action.sa_sigaction = handler as libc::sighandler_t;
+    action.sa_flags = if restart { libc::SA_RESTART } else { 0 };
+    let mut old: libc::sigaction = unsafe { std::mem::zeroed() };
+    unsafe { libc::sigemptyset(&mut action.sa_mask) };
+    if unsafe { libc::sigaction(sig, &action, &mut old) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(old)
+}
+
+/// Gives the signal sig back the action returned by catch.
+#[cfg(unix)]
+pub(crate) fn restore(sig: libc::c_int, old: &libc::sigaction) {
+    unsafe { libc::sigaction(sig, old, std::ptr::null_mut()) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clones_share_state() {
+        let token = CancelToken::new();
+        let clone = token.clone();
+        assert!(!clone.is_cancelled());
+        token.cancel();
+        assert!(clone.is_cancelled());
+    }
+}
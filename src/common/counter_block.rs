@@ -1,147 +1,227 @@
 #![warn(missing_debug_implementations, missing_docs)]
+use crate::cancel::CancelToken;
 use crate::error::*;
+use crate::exec::{Execution, Strategy};
 use crate::feistel;
 use crate::file_mng;
-use byteorder::{LittleEndian, WriteBytesExt};
+use crate::header::{self, EncHeader, KEY_CHECK_LEN};
+use crate::nonce::NonceSource;
+use crate::progress::{self, FileProgress, Progress};
+use crate::secret::Key;
 use glob::MatchOptions;
-use rand::Rng;
 use rayon::prelude::*;
+use std::cmp::{max, min};
+use std::fs::{metadata, File};
+use std::io::{Read, Write};
 use std::mem;
+use std::ops::Range;
+use std::slice::ChunksExact;
+
+/// The length of the random nonce. The nonce and the block counter appended to it fill exactly the
+/// narrowest feistel network, so every keystream block is generated from a full width input.
+const NONCE_LEN: usize = feistel::DEFAULT_WIDTH - mem::size_of::<u64>();
+
+/// The number of bytes every thread reads, encrypts and writes at a time when encrypting straight
+/// from one file to another.
+const IO_CHUNK_LEN: usize = 1 << 20;
+
+/// The result of reading the last n blocks of a file: the blocks, and the index of the first one.
+pub type TailResult = Result<(Blocks, u64), DecryptErr>;
+
+/// The files of a glob encrypted into Blocks, each with its path, or with the reason it failed.
+pub type ClearGlob = Vec<(String, Result<Blocks, EncryptErr>)>;
+
+/// The encrypted files of a glob read into Blocks, each with its path, or with the reason it
+/// failed.
+pub type EncGlob = Vec<(String, Result<Blocks, DecryptErr>)>;
 
 /// The Blocks struct is the basic object containing all the information for encrypting or
 /// decrypting a byte array. it is used for loading a byte array (from a file or a vector),
 /// manipulating it, and writing the results.
 #[derive(Debug)]
 pub struct Blocks {
+    /// version: the version of the header the blocks were read with, or header::FORMAT_VERSION
+    /// for blocks encrypted by this build. It tells how the keystream of wide blocks is generated.
+    pub version: u32,
     /// nonce: the random seed that is incremented for every block encryption.
     pub nonce: Vec<u8>,
     /// f_rounds: the number of fiestel rounds to preform
     pub f_rounds: i32,
-    /// blocks: the actual byte arrays.
-    pub blocks: Vec<Vec<u8>>,
+    /// block_size: the size of every block in data.
+    pub block_size: usize,
+    /// plaintext_len: the length of the clear text of the whole file, which tells the padding of
+    /// the last block from the clear text. None for files written before it was stored, whose
+    /// decrypted last block keeps its padding (trailing nulls).
+    pub plaintext_len: Option<u64>,
+    /// key_check: tells whether a key is the one the blocks were encrypted with (see
+    /// header::key_check). None for files written before it was stored.
+    pub key_check: Option<[u8; KEY_CHECK_LEN]>,
+    /// data: the actual bytes of all the blocks, one after the other. Its length is a multiple of
+    /// block_size.
+    pub data: Vec<u8>,
 }
 
 impl Blocks {
+    /// The number of blocks in the struct.
+    pub fn block_count(&self) -> usize {
+        self.data.len() / self.block_size
+    }
+
+    /// An iterator over the blocks of the struct, each block_size bytes long.
+    pub fn blocks(&self) -> ChunksExact<'_, u8> {
+        self.data.chunks_exact(self.block_size)
+    }
+
     /// Read a clear file and generate a Blocks struct containing the encrypted data.
     /// This method is inteded for use incase of a signle file encryption, and preforms the
-    /// encryption in parallel.
+    /// encryption in parallel. progress is called as the blocks are encrypted.
     pub fn from_clear_file(
         path: &str,
-        key: &str,
+        key: &Key,
         block_size: usize,
         f_rounds: i32,
+        nonce_source: &dyn NonceSource,
+        progress: &Progress,
     ) -> Result<Self, EncryptErr> {
         let f = file_mng::read_clear_file(path)?;
-        let pass = key.to_owned().into_bytes();
-        par_encrypt(f, pass, block_size, f_rounds)
+        par_encrypt(f, key, block_size, f_rounds, nonce_source, progress)
     }
 
     /// Read a glob of clear files and generate Blocks structs containing the encrypted data.
     /// this method is intended for use incase of multiple files encryption, and will encrypt the
     /// files in parallel, rather then encrypting the block of every individual file in parallel.
+    /// All the files are held in memory at once; to encrypt a glob of large files straight to
+    /// disk, use batch::encrypt_batch. progress is called with the path of every file as its
+    /// blocks are encrypted.
+    /// # Errors
+    /// returns an error only if path is not a valid glob pattern; the files fail one by one.
     pub fn from_clear_glob(
         path: &str,
-        key: &str,
+        key: &Key,
         block_size: usize,
         f_rounds: i32,
         options: MatchOptions,
-    ) -> Vec<(String, Result<Blocks, EncryptErr>)> {
-        let paths = file_mng::list_glob(path, options).unwrap();
+        nonce_source: &dyn NonceSource,
+        progress: &FileProgress,
+    ) -> Result<ClearGlob, EncryptErr> {
+        let paths =
+            file_mng::list_glob(path, options).map_err(|e| EncryptErr::IoError(e.to_string()))?;
         let res: Vec<(String, Result<Blocks, EncryptErr>)> = paths
             .into_par_iter()
             .map(|p| {
-                let b = Blocks::from_clear_file(&p, key, block_size, f_rounds);
+                let file_progress = |bytes, blocks| progress(&p, bytes, blocks);
+                let b = Blocks::from_clear_file(
+                    &p,
+                    key,
+                    block_size,
+                    f_rounds,
+                    nonce_source,
+                    &file_progress,
+                );
                 (p, b)
             })
             .collect();
-        res
+        Ok(res)
     }
 
     /// Read the contants of an encrypted file and generate a Blocks struct for it, parsing all the
     /// serialized variables (nonce, block size etc.). The Blocks struct will contain the encrypted
     /// data, which can then be decrypted with the into_clear method.
     pub fn from_enc_file(path: &str) -> Result<Self, DecryptErr> {
-        file_mng::read_enc_file(&path)
+        file_mng::read_enc_file(path)
     }
 
     /// Same as from_enc_file, but only reads the first n blocks of the file.
-    pub fn from_enc_head(path: &str, block_num: i32) -> Result<Self, DecryptErr> {
+    pub fn from_enc_head(path: &str, block_num: u64) -> Result<Self, DecryptErr> {
         file_mng::read_first_n(path, block_num)
     }
 
     /// Same as from_enc_file, but only reads the last n blocks of the file.
-    pub fn from_enc_tail(path: &str, block_num: i32) -> Result<(Self, i64), DecryptErr> {
+    pub fn from_enc_tail(path: &str, block_num: u64) -> TailResult {
         file_mng::read_last_n(path, block_num)
     }
 
     /// Read the contants of all encrypted files in a glob and generate a Blocks struct for it, parsing all the
     /// serialized variables (nonce, block size etc.). The Blocks struct will contain the encrypted
-    /// data, which can then be decrypted with the into_clear method.
+    /// data, which can then be decrypted with the into_clear method. progress is called with the
+    /// path of every file once it is read.
+    /// # Errors
+    /// returns an error only if path is not a valid glob pattern; the files fail one by one.
     pub fn from_enc_glob(
         path: &str,
         options: MatchOptions,
-    ) -> Vec<(String, Result<Self, DecryptErr>)> {
-        let paths = file_mng::list_glob(path, options).unwrap();
+        progress: &FileProgress,
+    ) -> Result<EncGlob, DecryptErr> {
+        let paths = list_enc_glob(path, options)?;
         let res: Vec<(String, Result<Self, DecryptErr>)> = paths
             .into_par_iter()
             .map(|p| {
                 let b = file_mng::read_enc_file(&p);
+                if let Ok(b) = &b {
+                    b.report_to(&p, progress);
+                }
                 (p, b)
             })
             .collect();
-        res
+        Ok(res)
     }
 
     /// Same as from_enc_glob, but only reads the first n blocks of every file.
     pub fn from_enc_glob_head(
         path: &str,
         options: MatchOptions,
-        block_num: i32,
-    ) -> Vec<(String, Result<Self, DecryptErr>)> {
-        let paths = file_mng::list_glob(path, options).unwrap();
+        block_num: u64,
+        progress: &FileProgress,
+    ) -> Result<EncGlob, DecryptErr> {
+        let paths = list_enc_glob(path, options)?;
         let res: Vec<(String, Result<Self, DecryptErr>)> = paths
             .into_par_iter()
             .map(|p| {
                 let b = file_mng::read_first_n(&p, block_num);
+                if let Ok(b) = &b {
+                    b.report_to(&p, progress);
+                }
                 (p, b)
             })
             .collect();
-        res
+        Ok(res)
     }
 
     /// Same as from_enc_glob, but only reads the last n blocks of every file.
     pub fn from_enc_glob_tail(
         path: &str,
         options: MatchOptions,
-        block_num: i32,
-    ) -> Vec<(String, Result<(Self, i64), DecryptErr>)> {
-        let paths = file_mng::list_glob(path, options).unwrap();
-        let res: Vec<(String, Result<(Self, i64), DecryptErr>)> = paths
+        block_num: u64,
+        progress: &FileProgress,
+    ) -> Result<Vec<(String, TailResult)>, DecryptErr> {
+        let paths = list_enc_glob(path, options)?;
+        let res: Vec<(String, TailResult)> = paths
             .into_par_iter()
             .map(|p| {
                 let b = file_mng::read_last_n(&p, block_num);
+                if let Ok((b, _)) = &b {
+                    b.report_to(&p, progress);
+                }
                 (p, b)
             })
             .collect();
-        res
+        Ok(res)
     }
 
     /// Given the correct key, consumes the struct and returns a decrypted byte vector containing the original data.
-    pub fn into_clear(self, key: &str, start_block: i64) -> Result<Vec<u8>, DecryptErr> {
-        let pass = key.to_owned().into_bytes();
-        par_decrypt(self, pass, start_block)
+    pub fn into_clear(self, key: &Key, start_block: u64) -> Result<Vec<u8>, DecryptErr> {
+        par_decrypt(self, key, start_block, &progress::ignore)
     }
 
     /// Given the correct key, consume the struct and write the decrypted contants of the struct to
     /// a file.
     pub fn into_clear_file(
         self,
-        key: &str,
+        key: &Key,
         path: &str,
-        start_block: i64,
+        start_block: u64,
     ) -> Result<(), DecryptErr> {
-        let pass = key.to_owned().into_bytes();
-        let dec = par_decrypt(self, pass, start_block)?;
+        let dec = par_decrypt(self, key, start_block, &progress::ignore)?;
         file_mng::write_clear_file(path, dec)
     }
 
@@ -149,134 +229,699 @@ impl Blocks {
     pub fn into_enc_file(self, path: &str) -> Result<(), EncryptErr> {
         file_mng::write_blocks(self, path)
     }
+
+    /// Reports all the blocks of the struct as done in the file at path.
+    fn report_to(&self, path: &str, progress: &FileProgress) {
+        progress(path, self.data.len() as u64, self.block_count() as u64);
+    }
+}
+
+/// The paths of the encrypted files matching the glob path.
+fn list_enc_glob(path: &str, options: MatchOptions) -> Result<Vec<String>, DecryptErr> {
+    file_mng::list_glob(path, options).map_err(|e| DecryptErr::IoError(e.to_string()))
 }
 
 /// Preformes a parallel block encryption, using Counter Block mode of operation, and fiestel
 /// cypher method.
+/// msg is encrypted in place: the last block is padded with trailing nulls, and the buffer becomes
+/// the data of the returned Blocks. progress is called as the blocks are encrypted (pass
+/// progress::ignore to ignore it).
 pub fn par_encrypt(
-    mut msg: Vec<u8>,
-    key: Vec<u8>,
+    msg: Vec<u8>,
+    key: &Key,
     block_size: usize,
     f_rounds: i32,
+    nonce_source: &dyn NonceSource,
+    progress: &Progress,
 ) -> Result<Blocks, EncryptErr> {
-    // TODO: assertions
-    // number of block nust be less then MAX::i64 because it can overflow the counter
-
-    let nonce_len: usize = 128 - mem::size_of::<i64>(); // nonce needs to be 128 bytes long beacuse of the use of SHA256, including the length of the counter
-    let nonce: Vec<u8> = nonce_gen(nonce_len);
-
-    let mut all_batches: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
-    for (counter, chunk) in msg.chunks_mut(block_size).enumerate() {
-        let nonce_counter: Vec<u8> = get_nonce_counter(&nonce, counter as i64);
-        let chunk_vec: Vec<u8> = chunk.to_vec();
-        all_batches.push((nonce_counter, chunk_vec));
-    }
-    let blocks_res: Vec<Result<Vec<u8>, EncryptErr>> = all_batches
-        .into_par_iter()
-        .map(|(nonce_counter, block)| {
-            encrypt_par_block(nonce_counter, block, key.clone(), f_rounds, block_size)
-        })
-        .collect();
+    encrypt_with(
+        msg,
+        key,
+        block_size,
+        f_rounds,
+        nonce_source,
+        &Strategy::PerBlock.into(),
+        progress,
+    )
+}
 
-    let mut blocks: Vec<Vec<u8>> = Vec::new();
-    for item in blocks_res.into_iter() {
-        match item {
-            Ok(i) => blocks.push(i),
-            Err(e) => return Err(e),
-        }
-    }
+/// Preformes a parallel block decryption using Counter Block mode of operation, and fiestel cypher
+/// method. The blocks are decrypted in place, and their buffer is returned. progress is called as
+/// the blocks are decrypted.
+pub fn par_decrypt(
+    b: Blocks,
+    key: &Key,
+    start_block: u64,
+    progress: &Progress,
+) -> Result<Vec<u8>, DecryptErr> {
+    decrypt_with(b, key, start_block, &Strategy::PerBlock.into(), progress)
+}
+
+/// Same as par_encrypt, but runs as exec says: blocks are encrypted in parallel (in the pool of
+/// exec) only if its strategy resolves to PerBlock for msg, and sequentially otherwise.
+pub fn encrypt_with(
+    mut msg: Vec<u8>,
+    key: &Key,
+    block_size: usize,
+    f_rounds: i32,
+    nonce_source: &dyn NonceSource,
+    exec: &Execution,
+    progress: &Progress,
+) -> Result<Blocks, EncryptErr> {
+    let nonce: Vec<u8> = nonce_source.nonce(NONCE_LEN);
+    let plaintext_len = msg.len() as u64;
+    pad_msg(&mut msg, block_size);
+    let cipher = Cipher {
+        version: header::FORMAT_VERSION,
+        nonce: &nonce,
+        key,
+        f_rounds,
+        block_size,
+    };
+    apply_with(exec, cipher, &mut msg, 0, progress)?;
 
     Ok(Blocks {
+        version: header::FORMAT_VERSION,
+        key_check: Some(header::key_check(key, &nonce)),
         nonce,
         f_rounds,
-        blocks,
+        block_size,
+        plaintext_len: Some(plaintext_len),
+        data: msg,
     })
 }
 
-// may cause trailing null problems!!!!
-/// Preformes a parallel block decryption using Counter Block mode of operation, and fiestel cypher
-/// method.
-pub fn par_decrypt(b: Blocks, key: Vec<u8>, start_block: i64) -> Result<Vec<u8>, DecryptErr> {
-    let (nonce, blocks) = (b.nonce, b.blocks);
-    let block_len = blocks[0].len();
-    let f_rounds = b.f_rounds;
-    let mut all_batches: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
-    let mut msg: Vec<u8> = Vec::with_capacity(blocks.len() * blocks[0].len());
-    for (counter, block) in blocks.into_iter().enumerate() {
-        let nonce_counter: Vec<u8> = get_nonce_counter(&nonce, counter as i64 + start_block);
-        all_batches.push((nonce_counter, block));
-    }
-
-    let decrypted_blocks: Vec<Result<Vec<u8>, EncryptErr>> = all_batches
-        .into_par_iter()
-        .map(|(nonce_counter, block)| {
-            encrypt_par_block(nonce_counter, block, key.clone(), f_rounds, block_len)
-        })
-        .collect();
+/// Same as par_decrypt, but runs as exec says, like encrypt_with.
+pub fn decrypt_with(
+    b: Blocks,
+    key: &Key,
+    start_block: u64,
+    exec: &Execution,
+    progress: &Progress,
+) -> Result<Vec<u8>, DecryptErr> {
+    let mut msg = b.data;
+    let cipher = Cipher {
+        version: b.version,
+        nonce: &b.nonce,
+        key,
+        f_rounds: b.f_rounds,
+        block_size: b.block_size,
+    };
+    apply_with(exec, cipher, &mut msg, start_block, progress)?;
+    msg.truncate(clear_len(
+        b.plaintext_len,
+        b.block_size,
+        start_block,
+        msg.len(),
+    ));
+    Ok(msg)
+}
+
+/// Encrypts the clear file at path into a new encrypted file at enc_path, without loading either
+/// of them into memory. The file is split into chunks of blocks, and every rayon worker reads its
+/// chunk, encrypts it and writes it straight to its final offset in the (preallocated) encrypted
+/// file, so reading, encryption and writing all overlap. Memory use is a chunk per thread.
+/// The result is the same as from_clear_file followed by into_enc_file, and like it, an existing
+/// file at enc_path is never overwritten.
+pub fn encrypt_file(
+    path: &str,
+    enc_path: &str,
+    key: &Key,
+    block_size: usize,
+    f_rounds: i32,
+    nonce_source: &dyn NonceSource,
+) -> Result<(), EncryptErr> {
+    encrypt_file_with(
+        path,
+        enc_path,
+        key,
+        block_size,
+        f_rounds,
+        nonce_source,
+        &Execution::default(),
+    )
+}
+
+/// Same as encrypt_file, but runs as exec says: the chunks are encrypted in parallel (in the pool
+/// of exec) only if its strategy resolves to PerBlock for the file, and sequentially otherwise.
+pub fn encrypt_file_with(
+    path: &str,
+    enc_path: &str,
+    key: &Key,
+    block_size: usize,
+    f_rounds: i32,
+    nonce_source: &dyn NonceSource,
+    exec: &Execution,
+) -> Result<(), EncryptErr> {
+    let chunking = Chunking {
+        blocks: max(1, IO_CHUNK_LEN / block_size),
+        parallel: exec.resolve(1, metadata(path)?.len()) == Strategy::PerBlock,
+        overwrite: false,
+        progress: &progress::ignore_files,
+        cancel: None,
+    };
+    exec.install(|| {
+        encrypt_file_chunked(
+            path,
+            enc_path,
+            key,
+            block_size,
+            f_rounds,
+            nonce_source,
+            chunking,
+        )
+    })
+}
 
-    for item in decrypted_blocks.into_iter() {
-        match item {
-            Ok(mut i) => msg.append(&mut i),
-            Err(e) => return Err(DecryptErr::from(e)),
+/// How encrypt_file_chunked splits a file: chunks of blocks blocks each. If parallel is false, the
+/// chunks are encrypted one after the other by the calling thread, with a single buffer, so memory
+/// use is exactly one chunk. overwrite tells whether an existing encrypted file is replaced,
+/// progress is called with the path of the file as every chunk is written, and once cancel is
+/// cancelled the file stops at its next block, and fails with Cancelled.
+#[derive(Clone, Copy)]
+pub(crate) struct Chunking<'a> {
+    pub(crate) blocks: usize,
+    pub(crate) parallel: bool,
+    pub(crate) overwrite: bool,
+    pub(crate) progress: &'a FileProgress<'a>,
+    pub(crate) cancel: Option<&'a CancelToken>,
+}
+
+/// encrypt_file, with the file split into chunks as given by chunking.
+pub(crate) fn encrypt_file_chunked(
+    path: &str,
+    enc_path: &str,
+    key: &Key,
+    block_size: usize,
+    f_rounds: i32,
+    nonce_source: &dyn NonceSource,
+    chunking: Chunking,
+) -> Result<(), EncryptErr> {
+    let clear = File::open(path)?;
+    let plaintext_len = clear.metadata()?.len();
+    let nonce: Vec<u8> = nonce_source.nonce(NONCE_LEN);
+    let key_check = header::key_check(key, &nonce);
+    let header = EncHeader::new(nonce, block_size, f_rounds, plaintext_len, key_check);
+    let enc = file_mng::create_enc_file(enc_path, &header, chunking.overwrite)?;
+    let chunk_count = header.block_count().div_ceil(chunking.blocks as u64);
+    encrypt_chunks(
+        path,
+        &clear,
+        enc.file(),
+        &header,
+        key,
+        chunking,
+        0..chunk_count,
+    )?;
+    // a file that grew while it was encrypted reads fine, but its header has the old length
+    if clear.metadata()?.len() != plaintext_len {
+        return Err(changed_len(path, plaintext_len));
+    }
+    enc.commit()?;
+    Ok(())
+}
+
+/// The error of a clear file at path which is no longer plaintext_len bytes long, the length its
+/// encryption started with.
+fn changed_len(path: &str, plaintext_len: u64) -> EncryptErr {
+    EncryptErr::IoError(format!(
+        "{} changed while it was encrypted, it is no longer {} bytes long",
+        path, plaintext_len
+    ))
+}
+
+/// Encrypts the chunks (numbered from 0, of chunking.blocks blocks each) in range of the clear
+/// file at path, opened as clear, into enc, created for header by create_enc_file. Every chunk is
+/// written at its final offset, so the chunks can be encrypted in any order.
+/// # Errors
+/// returns an error if a chunk of range starts past the clear text length of header, or if the
+/// file is shorter then that length by the time its chunk is read.
+fn encrypt_chunks(
+    path: &str,
+    clear: &File,
+    enc: &File,
+    header: &EncHeader,
+    key: &Key,
+    chunking: Chunking,
+    range: Range<u64>,
+) -> Result<(), EncryptErr> {
+    let chunk_blocks = chunking.blocks;
+    let block_size = header.block_size();
+    let plaintext_len = header.plaintext_len().unwrap_or(0);
+    let data_start = header.data_start();
+    let chunk_len = (chunk_blocks * block_size) as u64;
+    let cipher = Cipher {
+        version: header.version(),
+        nonce: header.nonce(),
+        key,
+        f_rounds: header.f_rounds(),
+        block_size,
+    };
+    let new_worker = || (Keystream::new(cipher), Vec::new());
+    let encrypt_chunk =
+        |(keystream, buff): &mut (Keystream, Vec<u8>), chunk: u64| -> Result<(), EncryptErr> {
+            let offset = chunk * chunk_len;
+            let left = plaintext_len.checked_sub(offset).ok_or_else(|| {
+                EncryptErr::IoError(format!("chunk {} starts past the end of {}", chunk, path))
+            })?;
+            buff.clear();
+            buff.resize(min(chunk_len, left) as usize, 0);
+            file_mng::read_at(clear, buff, offset).map_err(|e| match e.kind() {
+                std::io::ErrorKind::UnexpectedEof => changed_len(path, plaintext_len),
+                _ => e.into(),
+            })?;
+            pad_msg(buff, block_size);
+            let first_block = chunk * chunk_blocks as u64;
+            for (counter, block) in (first_block..).zip(buff.chunks_mut(block_size)) {
+                // the chunks of a batch can be hundreds of megabytes, so this is checked for every
+                // block rather then for every chunk
+                if chunking.cancel.is_some_and(CancelToken::is_cancelled) {
+                    return Err(EncryptErr::Cancelled);
+                }
+                keystream.apply(counter, block);
+            }
+            file_mng::write_at(enc, buff, data_start + offset)?;
+            (chunking.progress)(path, buff.len() as u64, (buff.len() / block_size) as u64);
+            Ok(())
+        };
+
+    if chunking.parallel {
+        range
+            .into_par_iter()
+            .try_for_each_init(new_worker, encrypt_chunk)
+    } else {
+        let mut worker = new_worker();
+        range
+            .into_iter()
+            .try_for_each(|chunk| encrypt_chunk(&mut worker, chunk))
+    }
+}
+
+/// Decrypts the encrypted file at path into out, a chunk at a time, so neither the file nor its
+/// clear text has to fit in memory. The blocks of every chunk are decrypted in parallel, and
+/// progress is called as they are. returns the header of the file.
+/// # Errors
+/// returns Cancelled once cancel is cancelled, with only part of the clear text written to out.
+pub fn decrypt_file_to(
+    path: &str,
+    key: &Key,
+    out: &mut dyn Write,
+    progress: &Progress,
+    cancel: Option<&CancelToken>,
+) -> Result<EncHeader, DecryptErr> {
+    let mut f = File::open(path)?;
+    let file_size = f.metadata()?.len();
+    let header = EncHeader::read_from(&mut f, file_size)?;
+    // the cursor is left at the first block by read_from
+    let block_count = Some(header.block_count());
+    decrypt_chunks(&mut f, &header, key, out, block_count, progress, cancel)?;
+    Ok(header)
+}
+
+/// Same as decrypt_file_to, but reads the encrypted file from input, a stream whose length is not
+/// known up front (stdin, for example). The clear text is written to out as it is decrypted, so if
+/// the stream turns out to be truncated or appended to, out already holds the blocks before the
+/// error. returns the header of the stream, with the number of blocks read.
+/// # Errors
+/// returns LengthMismatch if the blocks do not match the clear text length in the header,
+/// PartialBlock if a stream of an older layout does not end on a block boundary, and Cancelled once
+/// cancel is cancelled.
+pub fn decrypt_stream(
+    input: &mut dyn Read,
+    key: &Key,
+    out: &mut dyn Write,
+    cancel: Option<&CancelToken>,
+) -> Result<EncHeader, DecryptErr> {
+    let header = EncHeader::read_from_stream(input)?;
+    let block_count = match header.plaintext_len() {
+        Some(len) => {
+            let block_count = Some(header.block_count());
+            decrypt_chunks(
+                input,
+                &header,
+                key,
+                out,
+                block_count,
+                &progress::ignore,
+                cancel,
+            )?;
+            if input.read(&mut [0u8])? != 0 {
+                return Err(DecryptErr::LengthMismatch(len));
+            }
+            header.block_count()
+        }
+        None => decrypt_chunks(input, &header, key, out, None, &progress::ignore, cancel)?,
+    };
+    Ok(header.with_block_count(block_count))
+}
+
+/// Reads the blocks of header from r a chunk at a time, decrypts them and writes their clear text
+/// to out, until r ends or, if block_count is given, until that many blocks are read. returns the
+/// number of blocks read, or Cancelled if cancel is cancelled before the last chunk.
+fn decrypt_chunks(
+    r: &mut dyn Read,
+    header: &EncHeader,
+    key: &Key,
+    out: &mut dyn Write,
+    block_count: Option<u64>,
+    progress: &Progress,
+    cancel: Option<&CancelToken>,
+) -> Result<u64, DecryptErr> {
+    let cipher = Cipher {
+        version: header.version(),
+        nonce: header.nonce(),
+        key,
+        f_rounds: header.f_rounds(),
+        block_size: header.block_size(),
+    };
+    let block_size = header.block_size();
+    let chunk_blocks = max(1, IO_CHUNK_LEN / block_size) as u64;
+    let mut buff: Vec<u8> = Vec::new();
+    let mut start = 0;
+    loop {
+        let count = match block_count {
+            Some(n) if start == n => break,
+            Some(n) => min(chunk_blocks, n - start),
+            None => chunk_blocks,
+        };
+        if cancelled(cancel) {
+            return Err(DecryptErr::Cancelled);
+        }
+        buff.resize(count as usize * block_size, 0);
+        let read = match read_full(r, &mut buff, cancel) {
+            Ok(read) => read,
+            Err(_) if cancelled(cancel) => return Err(DecryptErr::Cancelled),
+            Err(e) => return Err(e.into()),
+        };
+        if read < buff.len() {
+            if let (Some(_), Some(len)) = (block_count, header.plaintext_len()) {
+                return Err(DecryptErr::LengthMismatch(len));
+            }
+            if block_count.is_some() || read % block_size != 0 {
+                return Err(DecryptErr::PartialBlock);
+            }
+            buff.truncate(read);
+        }
+        apply_with(
+            &Strategy::PerBlock.into(),
+            cipher,
+            &mut buff,
+            start,
+            progress,
+        )?;
+        let len = clear_len(header.plaintext_len(), block_size, start, buff.len());
+        out.write_all(&buff[..len])?;
+        start += (buff.len() / block_size) as u64;
+        if buff.len() < count as usize * block_size {
+            break;
         }
     }
+    Ok(start)
+}
+
+/// Encrypts the clear text read from input into out as a stream: the header is written first,
+/// then the blocks, a chunk at a time, so neither input nor out has to be a file (stdin and
+/// stdout, for example). The blocks of every chunk are encrypted in parallel.
+/// plaintext_len is the length of input if it is known, and then input has to be exactly that
+/// long. If it is None, the header is written with a clear text length of 0, and the returned
+/// header (which has the real length) has to be written over it once the stream ends, so out has
+/// to be a file then (see file_mng::rewrite_header). returns the header of the encrypted stream.
+/// # Errors
+/// returns an IoError if input is not plaintext_len bytes long, Cancelled once cancel is cancelled
+/// (out then holds part of the stream, so it should be thrown away), and any error reading or
+/// writing.
+#[allow(clippy::too_many_arguments)]
+pub fn encrypt_stream(
+    input: &mut dyn Read,
+    out: &mut dyn Write,
+    plaintext_len: Option<u64>,
+    key: &Key,
+    block_size: usize,
+    f_rounds: i32,
+    nonce_source: &dyn NonceSource,
+    cancel: Option<&CancelToken>,
+) -> Result<EncHeader, EncryptErr> {
+    let nonce: Vec<u8> = nonce_source.nonce(NONCE_LEN);
+    let key_check = header::key_check(key, &nonce);
+    let header = EncHeader::new(
+        nonce,
+        block_size,
+        f_rounds,
+        plaintext_len.unwrap_or(0),
+        key_check,
+    );
+    header.write_to(out)?;
+    let cipher = Cipher {
+        version: header.version(),
+        nonce: header.nonce(),
+        key,
+        f_rounds,
+        block_size,
+    };
+    let chunk_len = max(1, IO_CHUNK_LEN / block_size) * block_size;
+    let mut buff: Vec<u8> = Vec::new();
+    let mut total: u64 = 0;
+    loop {
+        if cancelled(cancel) {
+            return Err(EncryptErr::Cancelled);
+        }
+        buff.resize(chunk_len, 0);
+        let read = match read_full(input, &mut buff, cancel) {
+            Ok(read) => read,
+            Err(_) if cancelled(cancel) => return Err(EncryptErr::Cancelled),
+            Err(e) => return Err(e.into()),
+        };
+        buff.truncate(read);
+        pad_msg(&mut buff, block_size);
+        apply_with(
+            &Strategy::PerBlock.into(),
+            cipher,
+            &mut buff,
+            total / block_size as u64,
+            &progress::ignore,
+        )?;
+        out.write_all(&buff)?;
+        total += read as u64;
+        if read < chunk_len {
+            break;
+        }
+    }
+    match plaintext_len {
+        Some(len) if len != total => Err(EncryptErr::IoError(format!(
+            "the input is {} bytes long, not {}",
+            total, len
+        ))),
+        _ => Ok(EncHeader::new(
+            header.nonce().to_vec(),
+            block_size,
+            f_rounds,
+            total,
+            key_check,
+        )),
+    }
+}
+
+/// Reads from r until buff is full or r ends. returns the number of bytes read. A read
+/// interrupted by a signal is tried again, unless cancel was cancelled by it (see
+/// cancel::on_interrupt): then the Interrupted error is returned, so a read blocked on stdin stops
+/// at ctrl-c. Callers tell it from other errors with cancelled.
+fn read_full(
+    r: &mut dyn Read,
+    buff: &mut [u8],
+    cancel: Option<&CancelToken>,
+) -> std::io::Result<usize> {
+    let mut read = 0;
+    while read < buff.len() {
+        match r.read(&mut buff[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted && !cancelled(cancel) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+/// Whether there is a cancel token, and it was cancelled.
+fn cancelled(cancel: Option<&CancelToken>) -> bool {
+    cancel.is_some_and(CancelToken::is_cancelled)
+}
+
+/// Same as par_decrypt, but decrypts blocks borrowed from elsewhere (a memory mapped file, for
+/// example) into a new buffer, instead of consuming a Blocks struct. The padding of the last block
+/// is not removed; see clear_len. The blocks are of the file of header, and progress is called as
+/// they are decrypted.
+pub fn par_decrypt_slice(
+    header: &EncHeader,
+    data: &[u8],
+    key: &Key,
+    start_block: u64,
+    progress: &Progress,
+) -> Result<Vec<u8>, DecryptErr> {
+    let mut msg = data.to_vec();
+    let cipher = Cipher {
+        version: header.version(),
+        nonce: header.nonce(),
+        key,
+        f_rounds: header.f_rounds(),
+        block_size: header.block_size(),
+    };
+    apply_with(
+        &Strategy::PerBlock.into(),
+        cipher,
+        &mut msg,
+        start_block,
+        progress,
+    )?;
     Ok(msg)
 }
 
-/// The function used by par_encrypt to preform the actual encryption of every block of the
-/// messege.
-fn encrypt_par_block(
-    nonce: Vec<u8>,
-    mut chunk: Vec<u8>,
-    key: Vec<u8>,
+/// xors every block of msg with its keystream, in parallel or not as exec says. The blocks are
+/// handled a chunk at a time, and progress is called after every chunk.
+/// # Errors
+/// returns EncryptErr::CounterOverflow if the counters of the blocks do not fit in the counter
+/// space.
+fn apply_with(
+    exec: &Execution,
+    cipher: Cipher,
+    msg: &mut [u8],
+    start_block: u64,
+    progress: &Progress,
+) -> Result<(), EncryptErr> {
+    let block_size = cipher.block_size;
+    let block_count = msg.len().div_ceil(block_size) as u64;
+    if start_block.checked_add(block_count).is_none() {
+        return Err(EncryptErr::CounterOverflow);
+    }
+    let parallel = exec.resolve(1, msg.len() as u64) == Strategy::PerBlock;
+    // a chunk for every thread, so calling progress does not hold the threads back
+    let threads = if parallel { exec.threads() } else { 1 };
+    let chunk_blocks = max(1, IO_CHUNK_LEN / block_size) * threads;
+    let mut keystream: Option<Keystream> = None;
+    let mut first_block = start_block;
+    for chunk in msg.chunks_mut(chunk_blocks * block_size) {
+        if parallel {
+            exec.install(|| par_apply(cipher, chunk, first_block));
+        } else {
+            let keystream = keystream.get_or_insert_with(|| Keystream::new(cipher));
+            for (counter, block) in (first_block..).zip(chunk.chunks_mut(block_size)) {
+                keystream.apply(counter, block);
+            }
+        }
+        let blocks = chunk.len().div_ceil(block_size) as u64;
+        progress(chunk.len() as u64, blocks);
+        first_block += blocks;
+    }
+    Ok(())
+}
+
+/// xors every block of msg with its keystream in parallel, which both encrypts and decrypts it.
+/// The first block of msg is block number start_block of the file.
+fn par_apply(cipher: Cipher, msg: &mut [u8], start_block: u64) {
+    msg.par_chunks_mut(cipher.block_size)
+        .enumerate()
+        .for_each_init(
+            || Keystream::new(cipher),
+            |keystream, (counter, block)| keystream.apply(counter as u64 + start_block, block),
+        );
+}
+
+/// What the keystream of a file is generated from: the version of its header, its nonce, the key,
+/// the number of feistel rounds and the block size.
+#[derive(Clone, Copy)]
+struct Cipher<'a> {
+    version: u32,
+    nonce: &'a [u8],
+    key: &'a Key,
     f_rounds: i32,
     block_size: usize,
-) -> Result<Vec<u8>, EncryptErr> {
-    let mut cypher = feistel::encrypt(nonce, key, f_rounds)?;
-    pad_cypher(&mut cypher, block_size);
-    pad_chunk(&mut chunk, block_size);
-    chunk
-        .iter_mut()
-        .zip(cypher.iter())
-        .for_each(|(x1, x2)| *x1 ^= *x2);
-    Ok(chunk)
-}
-
-/// Generates a random nonce to be used in the ecnryption algorithm.
-fn nonce_gen(nonce_len: usize) -> Vec<u8> {
-    let v: Vec<u8> = (0..nonce_len)
-        .map(|_| {
-            let r: u8 = rand::thread_rng().gen();
-            r
-        })
-        .collect();
-    v
 }
 
-/// Appends an i64 counter to the nonce in orded to mutate it for every block.
-fn get_nonce_counter(nonce: &[u8], counter: i64) -> Vec<u8> {
-    let mut nonce_counter = nonce.to_vec();
-    let mut buff = [0u8; mem::size_of::<i64>()];
-    buff.as_mut().write_i64::<LittleEndian>(counter).unwrap();
-    nonce_counter.append(&mut buff.to_vec());
-    nonce_counter
+/// Generates the keystream of a file one block at a time. The keystream of a block is generated by
+/// running the nonce and the block counter through a feistel network at least as wide as the
+/// block, and truncating it to the block size. Blocks of up to feistel::DEFAULT_WIDTH bytes use the
+/// default width, so existing files keep their keystream. Files with legacy headers always used the
+/// default width, and repeated its output to fill wider blocks (see keystream_width).
+/// Every thread creates its own Keystream, which reuses its buffers for all the blocks it handles.
+struct Keystream {
+    nonce_counter: Vec<u8>,
+    nonce_len: usize,
+    network: feistel::Network,
 }
 
-/// pads the cypher generated from xor-ing the nonce with the key, in preperation for xor-ing it
-/// with the messege block. The padding is done by duplicating the cypher and then truncating it to
-/// size.
-fn pad_cypher(cypher: &mut Vec<u8>, block_size: usize) {
-    while cypher.len() < block_size {
-        cypher.append(&mut cypher.clone());
+impl Keystream {
+    fn new(cipher: Cipher) -> Self {
+        let mut nonce_counter = Vec::with_capacity(cipher.nonce.len() + mem::size_of::<u64>());
+        nonce_counter.extend_from_slice(cipher.nonce);
+        Keystream {
+            nonce_counter,
+            nonce_len: cipher.nonce.len(),
+            network: feistel::Network::new(
+                cipher.key,
+                cipher.f_rounds,
+                keystream_width(cipher.version, cipher.block_size),
+                feistel::DEFAULT_KEY_LEN,
+            ),
+        }
+    }
+
+    /// xors block (encrypting or decrypting it) with the keystream of block number counter.
+    fn apply(&mut self, counter: u64, block: &mut [u8]) {
+        // the counter is appended to the nonce in orded to mutate it for every block.
+        self.nonce_counter.truncate(self.nonce_len);
+        self.nonce_counter.extend_from_slice(&counter.to_le_bytes());
+        let cypher = self.network.encrypt(&self.nonce_counter);
+        // only the network of a legacy file can be narrower then its blocks
+        block
+            .iter_mut()
+            .zip(cypher.iter().cycle())
+            .for_each(|(x1, x2)| *x1 ^= *x2);
     }
-    cypher.truncate(block_size)
 }
 
-/// Pads the messege block in preperation for xor-ing it with the cypher. The padding is trailing
-/// nulls
-fn pad_chunk(chunk: &mut Vec<u8>, block_size: usize) {
-    for _ in 0..block_size - chunk.len() {
-        chunk.push(b'\x00');
+/// The width of the feistel network used for blocks of block_size bytes in a file with a header of
+/// version: the block size rounded up to an even number, and no less then feistel::DEFAULT_WIDTH.
+/// Files with legacy headers were encrypted before the width followed the block size, so their
+/// network is always feistel::DEFAULT_WIDTH wide.
+fn keystream_width(version: u32, block_size: usize) -> usize {
+    if version == header::LEGACY_VERSION {
+        feistel::DEFAULT_WIDTH
+    } else {
+        max(feistel::DEFAULT_WIDTH, block_size + block_size % 2)
+    }
+}
+
+/// The number of clear bytes in data_len bytes of decrypted blocks, the first of which is block
+/// number start_block of a file of plaintext_len clear bytes: the rest is the padding of the last
+/// block. Without plaintext_len, all of it is taken as clear text.
+pub(crate) fn clear_len(
+    plaintext_len: Option<u64>,
+    block_size: usize,
+    start_block: u64,
+    data_len: usize,
+) -> usize {
+    match plaintext_len {
+        Some(len) => {
+            let start = start_block.saturating_mul(block_size as u64);
+            min(data_len as u64, len.saturating_sub(start)) as usize
+        }
+        None => data_len,
+    }
+}
+
+/// The longest nonce that blocks of block_size bytes in a file with a header of version can be
+/// encrypted with: the nonce and the block counter together have to fit in the feistel network of
+/// the keystream.
+pub(crate) fn max_nonce_len(version: u32, block_size: usize) -> usize {
+    keystream_width(version, block_size) - mem::size_of::<u64>()
+}
+
+/// Pads the messege with trailing nulls to a multiple of the block size, in preperation for xor-ing
+/// its blocks with the cypher.
+fn pad_msg(msg: &mut Vec<u8>, block_size: usize) {
+    let rem = msg.len() % block_size;
+    if rem != 0 {
+        msg.resize(msg.len() + block_size - rem, b'\x00');
     }
 }
 
@@ -284,70 +929,52 @@ fn pad_chunk(chunk: &mut Vec<u8>, block_size: usize) {
 /// This function is intended for use incase of encrypting of multiple files, where the files are
 /// encrypted in parallel, not the blocks of every individual file.
 pub fn encrypt(
-    mut msg: Vec<u8>,
-    key: Vec<u8>,
+    msg: Vec<u8>,
+    key: &Key,
     block_size: usize,
     f_rounds: i32,
+    nonce_source: &dyn NonceSource,
 ) -> Result<Blocks, EncryptErr> {
-    // TODO: remove function
-    //assertions
-    // number of block nust be less then MAX::i64 because it can overflow the counter
-
-    let nonce_len: usize = 128 - mem::size_of::<i64>(); // nonce needs to be 128 bytes long beacuse of the use of SHA256, including the length of the counter
-    let nonce: Vec<u8> = nonce_gen(nonce_len);
-    let mut blocks: Vec<Vec<u8>> = Vec::with_capacity(msg.len() / block_size);
-
-    for (counter, chunk) in msg.chunks_mut(block_size).enumerate() {
-        let nonce_counter: Vec<u8> = get_nonce_counter(&nonce, counter as i64);
-        let mut cypher = feistel::encrypt(nonce_counter, key.clone(), f_rounds)?;
-        let mut chunk = chunk.to_vec();
-        pad_cypher(&mut cypher, block_size);
-        pad_chunk(&mut chunk, block_size);
-        chunk
-            .iter_mut()
-            .zip(cypher.iter())
-            .for_each(|(x1, x2)| *x1 ^= *x2);
-        blocks.push(chunk.to_vec());
-    }
-    Ok(Blocks {
-        nonce,
+    encrypt_with(
+        msg,
+        key,
+        block_size,
         f_rounds,
-        blocks,
-    })
+        nonce_source,
+        &Strategy::Sequential.into(),
+        &progress::ignore,
+    )
 }
 
 /// Non-parallel decryption of a byte array. returns a Blocks struct containing the decrypted data.
 /// This function is intended for use incase of decrypting of multiple files, where the files are
 /// decrypted in parallel, not the blocks of every individual file.
-pub fn decrypt(b: Blocks, key: Vec<u8>, start_block: i64) -> Result<Vec<u8>, DecryptErr> {
-    let nonce = b.nonce;
-    let mut blocks = b.blocks;
-    let mut counter: i64 = start_block;
-    let mut msg: Vec<u8> = Vec::with_capacity(blocks.len() * blocks[0].len());
-    for mut block in blocks.iter_mut() {
-        let nonce_counter: Vec<u8> = get_nonce_counter(&nonce, counter);
-        let mut cypher = feistel::encrypt(nonce_counter, key.clone(), b.f_rounds)?;
-        pad_cypher(&mut cypher, block.len());
-        block
-            .iter_mut()
-            .zip(cypher.iter())
-            .for_each(|(x1, x2)| *x1 ^= *x2);
-        msg.append(&mut block);
-        counter += 1;
-    }
-    Ok(msg)
+pub fn decrypt(b: Blocks, key: &Key, start_block: u64) -> Result<Vec<u8>, DecryptErr> {
+    decrypt_with(
+        b,
+        key,
+        start_block,
+        &Strategy::Sequential.into(),
+        &progress::ignore,
+    )
 }
 
 #[cfg(test)]
 mod tests {
     use crate::counter_block;
+    use crate::exec::{Execution, Strategy};
+    use crate::nonce::{OsNonce, SeededNonce};
+    use crate::progress;
+    use crate::secret::Key;
+    use crate::test_util::{key, temp_path, testdata};
     #[test]
     fn par_enc_dec_bytes() {
         let msg = String::from("hello world, this is my string! it may contain אותיות בעברית")
             .into_bytes();
-        let key = String::from("super_secret123!@#").into_bytes();
-        let blocks = counter_block::par_encrypt(msg, key.clone(), 15, 5).unwrap();
-        let dec = counter_block::par_decrypt(blocks, key, 0).unwrap();
+        let key = key();
+        let blocks =
+            counter_block::par_encrypt(msg, &key, 15, 5, &OsNonce, &progress::ignore).unwrap();
+        let dec = counter_block::par_decrypt(blocks, &key, 0, &progress::ignore).unwrap();
 
         assert_eq!(
             String::from_utf8(dec).unwrap().trim_matches(char::from(0)),
@@ -355,6 +982,31 @@ mod tests {
         );
     }
 
+    #[test]
+    fn par_enc_dec_wide_blocks() {
+        let msg: Vec<u8> = (0..1000).map(|x| (x % 251) as u8).collect();
+        let key = key();
+        let blocks =
+            counter_block::par_encrypt(msg.clone(), &key, 301, 5, &OsNonce, &progress::ignore)
+                .unwrap();
+        assert_eq!(blocks.block_count(), 4);
+        assert_eq!(blocks.data.len(), 4 * 301);
+
+        let dec = counter_block::par_decrypt(blocks, &key, 0, &progress::ignore).unwrap();
+        assert_eq!(dec[..msg.len()], msg[..]);
+    }
+
+    #[test]
+    fn wide_keystream_does_not_repeat() {
+        let msg = vec![0u8; 512];
+        let key = key();
+        let blocks =
+            counter_block::par_encrypt(msg, &key, 512, 5, &OsNonce, &progress::ignore).unwrap();
+        let keystream = &blocks.data;
+        assert_ne!(keystream[..128], keystream[128..256]);
+        assert_ne!(keystream[..256], keystream[256..]);
+    }
+
     #[test]
     fn enc_block_num() {
         // TODO:
@@ -364,10 +1016,11 @@ mod tests {
     fn dec_wrong_key() {
         let msg = String::from("hello world, this is my string! it may contain אותיות בעברית")
             .into_bytes();
-        let key = String::from("super_secret123!@#").into_bytes();
-        let wrong_key = String::from("incorrect!").into_bytes();
-        let blocks = counter_block::par_encrypt(msg, key.clone(), 15, 5).unwrap();
-        let dec = counter_block::par_decrypt(blocks, wrong_key, 0).unwrap();
+        let key = key();
+        let wrong_key = Key::from("incorrect!");
+        let blocks =
+            counter_block::par_encrypt(msg, &key, 15, 5, &OsNonce, &progress::ignore).unwrap();
+        let dec = counter_block::par_decrypt(blocks, &wrong_key, 0, &progress::ignore).unwrap();
 
         assert_ne!(
             dec,
@@ -380,11 +1033,383 @@ mod tests {
     fn enc_twice() {
         let msg = String::from("hello world, this is my string! it may contain אותיות בעברית")
             .into_bytes();
-        let key = String::from("super_secret123!@#").into_bytes();
-        let blocks1 = counter_block::par_encrypt(msg.clone(), key.clone(), 15, 5).unwrap();
-        let blocks2 = counter_block::par_encrypt(msg, key.clone(), 15, 5).unwrap();
+        let key = key();
+        let blocks1 =
+            counter_block::par_encrypt(msg.clone(), &key, 15, 5, &OsNonce, &progress::ignore)
+                .unwrap();
+        let blocks2 =
+            counter_block::par_encrypt(msg, &key, 15, 5, &OsNonce, &progress::ignore).unwrap();
 
         assert_ne!(blocks1.nonce, blocks2.nonce);
-        assert_ne!(blocks1.blocks, blocks2.blocks);
+        assert_ne!(blocks1.data, blocks2.data);
+    }
+
+    #[test]
+    fn strategies_agree() {
+        let msg: Vec<u8> = (0..100_000).map(|x| (x % 251) as u8).collect();
+        let key = key();
+        let custom = Execution::with_threads(Strategy::PerBlock, 2).unwrap();
+        let results: Vec<Vec<u8>> = [
+            Strategy::Sequential.into(),
+            Strategy::PerBlock.into(),
+            Strategy::Auto.into(),
+            custom,
+        ]
+        .iter()
+        .map(|exec| {
+            let b = counter_block::encrypt_with(
+                msg.clone(),
+                &key,
+                100,
+                5,
+                &SeededNonce::new(3),
+                exec,
+                &progress::ignore,
+            )
+            .unwrap();
+            b.data
+        })
+        .collect();
+        assert!(results.iter().all(|r| *r == results[0]));
+    }
+
+    #[test]
+    fn seeded_enc_is_reproducible() {
+        let msg = String::from("hello world, this is my string! it may contain אותיות בעברית")
+            .into_bytes();
+        let key = key();
+        let blocks1 = counter_block::par_encrypt(
+            msg.clone(),
+            &key,
+            15,
+            5,
+            &SeededNonce::new(1),
+            &progress::ignore,
+        )
+        .unwrap();
+        let blocks2 = counter_block::encrypt(msg, &key, 15, 5, &SeededNonce::new(1)).unwrap();
+
+        assert_eq!(blocks1.nonce, blocks2.nonce);
+        assert_eq!(blocks1.data, blocks2.data);
+    }
+
+    #[test]
+    fn streams_match_files() {
+        use crate::cancel::CancelToken;
+        use crate::error::{DecryptErr, EncryptErr};
+        use crate::header::EncHeader;
+        use std::fs;
+        let key = key();
+        let clear = fs::read(testdata("clear.txt")).unwrap();
+
+        // the same bytes as the file encryption of the golden file, with the length known or not
+        let golden = fs::read(testdata("golden_30.enc")).unwrap();
+        for &len in [Some(clear.len() as u64), None].iter() {
+            let mut enc: Vec<u8> = Vec::new();
+            let header = counter_block::encrypt_stream(
+                &mut &clear[..],
+                &mut enc,
+                len,
+                &key,
+                30,
+                5,
+                &SeededNonce::new(42),
+                None,
+            )
+            .unwrap();
+            let header_len = header.data_start() as usize;
+            enc[..header_len].copy_from_slice(&header.to_bytes());
+            assert_eq!(enc, golden);
+        }
+        let short = counter_block::encrypt_stream(
+            &mut &clear[1..],
+            &mut Vec::new(),
+            Some(clear.len() as u64),
+            &key,
+            30,
+            5,
+            &OsNonce,
+            None,
+        );
+        assert!(short.is_err());
+
+        for name in &[
+            "golden_30.enc",
+            "golden_301.enc",
+            "legacy_30.enc",
+            "legacy_301.enc",
+        ] {
+            let enc = fs::read(testdata(name)).unwrap();
+            let mut dec: Vec<u8> = Vec::new();
+            let header =
+                counter_block::decrypt_stream(&mut &enc[..], &key, &mut dec, None).unwrap();
+            assert_eq!(&dec[..clear.len()], &clear[..], "{}", name);
+            let file_header = EncHeader::read_from(&mut &enc[..], enc.len() as u64).unwrap();
+            assert_eq!(header, file_header, "{}", name);
+        }
+
+        let enc = fs::read(testdata("golden_30.enc")).unwrap();
+        let dec = |bytes: &[u8]| {
+            counter_block::decrypt_stream(&mut &bytes[..], &key, &mut Vec::new(), None)
+        };
+        assert!(matches!(
+            dec(&enc[..enc.len() - 30]),
+            Err(DecryptErr::LengthMismatch(_))
+        ));
+        let mut appended = enc.clone();
+        appended.push(0);
+        assert!(matches!(dec(&appended), Err(DecryptErr::LengthMismatch(_))));
+        let legacy = fs::read(testdata("legacy_30.enc")).unwrap();
+        assert!(matches!(
+            dec(&legacy[..legacy.len() - 1]),
+            Err(DecryptErr::PartialBlock)
+        ));
+
+        // a cancelled token stops both before their first chunk
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let mut dec: Vec<u8> = Vec::new();
+        let res = counter_block::decrypt_stream(&mut &enc[..], &key, &mut dec, Some(&cancel));
+        assert!(matches!(res, Err(DecryptErr::Cancelled)));
+        assert!(dec.is_empty());
+        let res = counter_block::encrypt_stream(
+            &mut &clear[..],
+            &mut Vec::new(),
+            None,
+            &key,
+            30,
+            5,
+            &OsNonce,
+            Some(&cancel),
+        );
+        assert!(matches!(res, Err(EncryptErr::Cancelled)));
+    }
+
+    /// A reader of data whose first read is interrupted by a signal, which cancels cancel (like
+    /// ctrl-c with cancel::on_interrupt).
+    struct Interrupted<'a> {
+        data: &'a [u8],
+        cancel: Option<&'a crate::cancel::CancelToken>,
+        interrupted: bool,
+    }
+
+    impl std::io::Read for Interrupted<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if !self.interrupted {
+                self.interrupted = true;
+                if let Some(cancel) = self.cancel {
+                    cancel.cancel();
+                }
+                return Err(std::io::ErrorKind::Interrupted.into());
+            }
+            self.data.read(buf)
+        }
+    }
+
+    #[test]
+    fn interrupted_streams() {
+        use crate::cancel::CancelToken;
+        use crate::error::{DecryptErr, EncryptErr};
+        let key = key();
+        let clear: Vec<u8> = (0..1000).map(|x| (x % 251) as u8).collect();
+        let encrypt = |input: &mut Interrupted, out: &mut Vec<u8>, cancel| {
+            let len = Some(clear.len() as u64);
+            counter_block::encrypt_stream(input, out, len, &key, 30, 5, &OsNonce, cancel)
+        };
+
+        // without a cancelled token, the read is tried again
+        let mut enc: Vec<u8> = Vec::new();
+        let mut input = Interrupted {
+            data: &clear,
+            cancel: None,
+            interrupted: false,
+        };
+        encrypt(&mut input, &mut enc, None).unwrap();
+        let mut input = Interrupted {
+            data: &enc,
+            cancel: None,
+            interrupted: false,
+        };
+        let mut dec: Vec<u8> = Vec::new();
+        counter_block::decrypt_stream(&mut input, &key, &mut dec, None).unwrap();
+        assert_eq!(dec, clear);
+
+        // a signal that cancels the token stops the read where it blocked
+        let cancel = CancelToken::new();
+        let mut input = Interrupted {
+            data: &clear,
+            cancel: Some(&cancel),
+            interrupted: false,
+        };
+        let res = encrypt(&mut input, &mut Vec::new(), Some(&cancel));
+        assert!(matches!(res, Err(EncryptErr::Cancelled)), "{:?}", res);
+        let cancel = CancelToken::new();
+        // the header is read before the first interrupted read
+        let mut header = &enc[..300];
+        let mut input = Interrupted {
+            data: &enc[300..],
+            cancel: Some(&cancel),
+            interrupted: false,
+        };
+        let mut chained = std::io::Read::chain(&mut header, &mut input);
+        let res = counter_block::decrypt_stream(&mut chained, &key, &mut Vec::new(), Some(&cancel));
+        assert!(matches!(res, Err(DecryptErr::Cancelled)), "{:?}", res);
+    }
+
+    #[test]
+    fn bad_glob_pattern() {
+        use glob::MatchOptions;
+        let key = key();
+        let clear = counter_block::Blocks::from_clear_glob(
+            "a[",
+            &key,
+            15,
+            5,
+            MatchOptions::new(),
+            &OsNonce,
+            &progress::ignore_files,
+        );
+        assert!(clear.is_err());
+        assert!(counter_block::Blocks::from_enc_glob(
+            "a[",
+            MatchOptions::new(),
+            &progress::ignore_files
+        )
+        .is_err());
+        assert!(counter_block::Blocks::from_enc_glob_tail(
+            "a[",
+            MatchOptions::new(),
+            3,
+            &progress::ignore_files
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn progress_adds_up() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        let key = key();
+        let msg: Vec<u8> = (0..1_100_005).map(|x| (x % 251) as u8).collect();
+        let (bytes, blocks, calls) = (AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0));
+        let progress = |b, n| {
+            bytes.fetch_add(b, Ordering::Relaxed);
+            blocks.fetch_add(n, Ordering::Relaxed);
+            calls.fetch_add(1, Ordering::Relaxed);
+        };
+        let enc = counter_block::encrypt_with(
+            msg,
+            &key,
+            100,
+            5,
+            &OsNonce,
+            &Strategy::Sequential.into(),
+            &progress,
+        )
+        .unwrap();
+        counter_block::decrypt_with(enc, &key, 0, &Strategy::PerBlock.into(), &progress).unwrap();
+        // encrypted and decrypted, padded to whole blocks, a chunk at a time
+        assert_eq!(bytes.load(Ordering::Relaxed), 2 * 1_100_100);
+        assert_eq!(blocks.load(Ordering::Relaxed), 2 * 11_001);
+        assert!(calls.load(Ordering::Relaxed) >= 3);
+    }
+
+    // files past 4 GiB need u64 offsets. Encrypting all of one takes minutes, so only the chunks
+    // on both sides of the 4 GiB offset of a sparse file are encrypted, by the same code as
+    // encrypt_file_chunked, with chunks of two blocks. They are read back with both the tail and
+    // the memory-mapped readers.
+    #[test]
+    fn chunks_past_4_gib() {
+        use crate::file_mng;
+        use crate::header::{self, EncHeader};
+        use crate::nonce::NonceSource;
+        use std::fs;
+        let key = key();
+        let block_size = 4096;
+        let len: u64 = (4 << 30) + 1000;
+        let clear_path = temp_path("chunks_past_4_gib");
+        let enc_path = temp_path("chunks_past_4_gib.enc");
+        let clear = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&clear_path)
+            .unwrap();
+        clear.set_len(len).unwrap();
+        file_mng::write_at(&clear, b"before", (4 << 30) - 6).unwrap();
+        file_mng::write_at(&clear, b"the end", len - 7).unwrap();
+
+        let nonce = SeededNonce::new(42).nonce(counter_block::NONCE_LEN);
+        let key_check = header::key_check(&key, &nonce);
+        let header = EncHeader::new(nonce, block_size, 5, len, key_check);
+        let enc = file_mng::create_enc_file(&enc_path, &header, false).unwrap();
+        let chunking = counter_block::Chunking {
+            blocks: 2,
+            parallel: true,
+            overwrite: false,
+            progress: &progress::ignore_files,
+            cancel: None,
+        };
+        // the last chunk starts at the 4 GiB offset, and the one before it ends there
+        let chunk_count = header.block_count().div_ceil(2);
+        let res = counter_block::encrypt_chunks(
+            &clear_path,
+            &clear,
+            enc.file(),
+            &header,
+            &key,
+            chunking,
+            chunk_count - 2..chunk_count,
+        );
+        let committed = enc.commit();
+        let tail = counter_block::Blocks::from_enc_tail(&enc_path, 3);
+        let mapped = file_mng::MappedEncFile::open(&enc_path).and_then(|m| {
+            let count = m.block_count();
+            m.decrypt(&key, count - 3..count, &progress::ignore)
+        });
+        fs::remove_file(&clear_path).unwrap();
+        fs::remove_file(&enc_path).unwrap();
+        res.unwrap();
+        committed.unwrap();
+
+        let (tail, block_num) = tail.unwrap();
+        assert_eq!(block_num, (4 << 30) / block_size as u64 - 2);
+        let dec = tail.into_clear(&key, block_num).unwrap();
+        assert_eq!(dec.len(), 2 * block_size + 1000);
+        assert_eq!(&dec[2 * block_size - 6..2 * block_size], b"before");
+        assert_eq!(&dec[dec.len() - 7..], b"the end");
+        assert_eq!(mapped.unwrap(), dec);
+    }
+
+    #[test]
+    fn clear_file_changes_length() {
+        use crate::error::EncryptErr;
+        use std::fs;
+        let key = key();
+        let clear: Vec<u8> = (0..1000).map(|x| (x % 251) as u8).collect();
+        for &len in [1500, 500].iter() {
+            let path = temp_path(&format!("changes_to_{}", len));
+            let enc_path = format!("{}.enc", path);
+            fs::write(&path, &clear).unwrap();
+            // the clear file grows or shrinks once its first chunk is written
+            let change = |_: &str, _, _| {
+                let f = fs::OpenOptions::new().write(true).open(&path).unwrap();
+                f.set_len(len).unwrap();
+            };
+            let chunking = counter_block::Chunking {
+                blocks: 2,
+                parallel: false,
+                overwrite: false,
+                progress: &change,
+                cancel: None,
+            };
+            let res = counter_block::encrypt_file_chunked(
+                &path, &enc_path, &key, 100, 5, &OsNonce, chunking,
+            );
+            let written = fs::metadata(&enc_path).is_ok();
+            fs::remove_file(&path).unwrap();
+            assert!(matches!(res, Err(EncryptErr::IoError(_))), "{:?}", res);
+            assert!(!written);
+        }
     }
 }
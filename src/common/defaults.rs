@@ -0,0 +1,30 @@
+/// The default size (in bytes) of an encrypted block.
+pub const BLOCK_SIZE: usize = 100;
+
+/// The smallest block size accepted.
+pub const MIN_BLOCK_SIZE: usize = 1;
+
+/// The largest block size accepted. Every block runs through a feistel network as wide as the
+/// block, so very large blocks are slow and hold a lot of memory per thread.
+pub const MAX_BLOCK_SIZE: usize = 1 << 20;
+
+/// The default number of feistel rounds.
+pub const ROUNDS: i32 = 5;
+
+/// The smallest number of feistel rounds accepted. Less rounds then this do not mix the nonce
+/// enough.
+pub const MIN_ROUNDS: i32 = 4;
+
+/// The largest number of feistel rounds accepted. Every round hashes every block again, so this
+/// keeps a corrupted header from making decryption run for ever.
+pub const MAX_ROUNDS: i32 = 64;
+
+/// The default suffix of the names of encrypted files.
+pub const ENC_SUFFIX: &str = "_enc";
+
+/// The default number of blocks read from the start (or end) of a file with -head (or -tail).
+pub const HEAD_TAIL_BLOCKS: u64 = 100;
+
+/// The default memory budget (in bytes) of batch encryption, shared by all the files encrypted at
+/// the same time.
+pub const BATCH_MEMORY: usize = 256 << 20;
@@ -1,18 +1,27 @@
 use std::error::Error;
 use std::fmt;
 
+/// the errors of the command line parsers. every variant holds what went wrong, for the message.
 #[derive(Debug)]
 pub enum ArgErr {
-    MissingArg,
-    ArgMismatch,
+    /// an argument (named by the string) is missing.
+    MissingArg(String),
+    /// arguments that can not be used together, or an argument given more then once.
+    ArgMismatch(String),
     UnknownArg(String),
+    InvalidValue(String),
 }
 
 impl Error for ArgErr {}
 
 impl fmt::Display for ArgErr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "argument error: \n{}", self)
+        match self {
+            ArgErr::MissingArg(s) => write!(f, "missing {}", s),
+            ArgErr::ArgMismatch(s) => write!(f, "{}", s),
+            ArgErr::UnknownArg(s) => write!(f, "unknown argument {}", s),
+            ArgErr::InvalidValue(s) => write!(f, "{}", s),
+        }
     }
 }
 
@@ -20,13 +29,29 @@ impl fmt::Display for ArgErr {
 pub enum EncryptErr {
     HashErr,
     IoError(String),
+    /// the block counters of the data do not fit in the counter space (u64).
+    CounterOverflow,
+    /// the blocks have no key check value to write to the header (they were read from a file
+    /// with a legacy header).
+    MissingKeyCheck,
+    /// the operation was stopped by its CancelToken.
+    Cancelled,
 }
 
 impl Error for EncryptErr {}
 
 impl fmt::Display for EncryptErr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "encryption error: \n{}", self)
+        write!(f, "encryption error: ")?;
+        match self {
+            EncryptErr::HashErr => write!(f, "hashing failed"),
+            EncryptErr::IoError(s) => write!(f, "{}", s),
+            EncryptErr::CounterOverflow => write!(f, "too many blocks for the block counter"),
+            EncryptErr::MissingKeyCheck => {
+                write!(f, "the blocks have no key check value, encrypt them again")
+            }
+            EncryptErr::Cancelled => write!(f, "cancelled"),
+        }
     }
 }
 
@@ -40,13 +65,71 @@ impl From<std::io::Error> for EncryptErr {
 pub enum DecryptErr {
     HashErr,
     IoError(String),
+    /// the block counters of the data do not fit in the counter space (u64).
+    CounterOverflow,
+    /// the file ends before its header does.
+    TruncatedHeader,
+    /// the header is of a format version this build can not read.
+    UnsupportedVersion(u32),
+    /// the block size in the header is out of range.
+    InvalidBlockSize(i64),
+    /// the nonce size in the header is negative, or too long for the block size.
+    InvalidNonceSize(i64),
+    /// the number of rounds in the header is out of range.
+    InvalidRounds(i32),
+    /// the file does not end on a block boundary, so it was truncated or appended to.
+    PartialBlock,
+    /// the number of blocks in the file does not match the clear text length in its header, so it
+    /// was truncated or appended to.
+    LengthMismatch(u64),
+    /// the operation was stopped by its CancelToken.
+    Cancelled,
 }
 
 impl Error for DecryptErr {}
 
 impl fmt::Display for DecryptErr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "decryption error: \n{}", self)
+        write!(f, "decryption error: ")?;
+        match self {
+            DecryptErr::HashErr => write!(f, "hashing failed"),
+            DecryptErr::IoError(s) => write!(f, "{}", s),
+            DecryptErr::CounterOverflow => write!(f, "too many blocks for the block counter"),
+            DecryptErr::TruncatedHeader => {
+                write!(f, "file not encrypted or corrupted: header is truncated")
+            }
+            DecryptErr::UnsupportedVersion(v) => write!(f, "unsupported format version {}", v),
+            DecryptErr::InvalidBlockSize(n) => {
+                write!(
+                    f,
+                    "file not encrypted or corrupted: invalid block size {}",
+                    n
+                )
+            }
+            DecryptErr::InvalidNonceSize(n) => {
+                write!(
+                    f,
+                    "file not encrypted or corrupted: invalid nonce size {}",
+                    n
+                )
+            }
+            DecryptErr::InvalidRounds(n) => {
+                write!(
+                    f,
+                    "file not encrypted or corrupted: invalid number of rounds {}",
+                    n
+                )
+            }
+            DecryptErr::PartialBlock => {
+                write!(f, "file corrupted: it does not end on a block boundary")
+            }
+            DecryptErr::LengthMismatch(n) => write!(
+                f,
+                "file corrupted: its blocks do not hold the {} bytes its header says",
+                n
+            ),
+            DecryptErr::Cancelled => write!(f, "cancelled"),
+        }
     }
 }
 
@@ -55,6 +138,11 @@ impl From<EncryptErr> for DecryptErr {
         match e {
             EncryptErr::HashErr => DecryptErr::HashErr,
             EncryptErr::IoError(s) => DecryptErr::IoError(s),
+            EncryptErr::CounterOverflow => DecryptErr::CounterOverflow,
+            EncryptErr::MissingKeyCheck => {
+                DecryptErr::IoError(String::from("the blocks have no key check value"))
+            }
+            EncryptErr::Cancelled => DecryptErr::Cancelled,
         }
     }
 }
@@ -64,3 +152,37 @@ impl From<std::io::Error> for DecryptErr {
         DecryptErr::IoError(format!("{}", e))
     }
 }
+
+#[derive(Debug)]
+pub enum KeyErr {
+    /// the key is empty, which nothing can be encrypted with.
+    Empty,
+    /// the password and its confirmation differ.
+    Mismatch,
+    /// the environment variable the key should be read from is not set.
+    NotSet(String),
+    /// the file descriptor the key should be read from is not open.
+    BadFd(i32),
+    IoError(String),
+}
+
+impl Error for KeyErr {}
+
+impl fmt::Display for KeyErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "key error: ")?;
+        match self {
+            KeyErr::Empty => write!(f, "the key is empty"),
+            KeyErr::Mismatch => write!(f, "the passwords do not match"),
+            KeyErr::NotSet(v) => write!(f, "environment variable {} is not set", v),
+            KeyErr::BadFd(fd) => write!(f, "file descriptor {} is not open", fd),
+            KeyErr::IoError(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl From<std::io::Error> for KeyErr {
+    fn from(e: std::io::Error) -> KeyErr {
+        KeyErr::IoError(format!("{}", e))
+    }
+}
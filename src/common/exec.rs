@@ -0,0 +1,111 @@
+#![warn(missing_debug_implementations, missing_docs)]
+use rayon::{ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
+use std::sync::Arc;
+
+/// Below this many bytes in total, the work of starting threads costs more then it saves, and
+/// Auto runs sequentially.
+const SEQUENTIAL_LEN: u64 = 64 << 10;
+
+/// How the work of encrypting or decrypting is split between threads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strategy {
+    /// Everything runs on the calling thread, one block after the other.
+    Sequential,
+    /// The blocks of every file are split between the threads. Best for a few large files.
+    PerBlock,
+    /// Every file is handled by a single thread, and the files are split between the threads.
+    /// Best for many small files.
+    PerFile,
+    /// Picks one of the others by the number of files and their total size.
+    #[default]
+    Auto,
+}
+
+impl Strategy {
+    /// Resolves Auto to a concrete strategy for file_count files of total_len bytes in total, run
+    /// on threads threads. Any other strategy is returned as is.
+    pub fn resolve(self, file_count: usize, total_len: u64, threads: usize) -> Strategy {
+        match self {
+            Strategy::Auto if total_len < SEQUENTIAL_LEN => Strategy::Sequential,
+            Strategy::Auto if file_count >= threads => Strategy::PerFile,
+            Strategy::Auto => Strategy::PerBlock,
+            s => s,
+        }
+    }
+}
+
+/// Where and how the parallel work runs: the strategy, and the rayon thread pool to run it in.
+/// Without a pool, the work runs in rayon's global pool.
+#[derive(Debug, Clone, Default)]
+pub struct Execution {
+    /// strategy: how the work is split between threads.
+    pub strategy: Strategy,
+    /// pool: the thread pool to run in, or None for the global pool.
+    pub pool: Option<Arc<ThreadPool>>,
+}
+
+impl From<Strategy> for Execution {
+    fn from(strategy: Strategy) -> Self {
+        Execution {
+            strategy,
+            pool: None,
+        }
+    }
+}
+
+impl Execution {
+    /// Runs with strategy in a new pool of threads threads.
+    /// # Errors
+    /// returns an error if the threads can not be created.
+    pub fn with_threads(strategy: Strategy, threads: usize) -> Result<Self, ThreadPoolBuildError> {
+        let pool = ThreadPoolBuilder::new().num_threads(threads).build()?;
+        Ok(Execution {
+            strategy,
+            pool: Some(Arc::new(pool)),
+        })
+    }
+
+    /// The number of threads the work runs on.
+    pub fn threads(&self) -> usize {
+        match &self.pool {
+            Some(pool) => pool.current_num_threads(),
+            None => rayon::current_num_threads(),
+        }
+    }
+
+    /// The strategy for file_count files of total_len bytes, with Auto resolved.
+    pub fn resolve(&self, file_count: usize, total_len: u64) -> Strategy {
+        self.strategy.resolve(file_count, total_len, self.threads())
+    }
+
+    /// Runs op in the pool, so every parallel iterator inside it uses the threads of the pool.
+    pub fn install<OP, R>(&self, op: OP) -> R
+    where
+        OP: FnOnce() -> R + Send,
+        R: Send,
+    {
+        match &self.pool {
+            Some(pool) => pool.install(op),
+            None => op(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn auto_resolves_by_size_and_count() {
+        assert_eq!(Strategy::Auto.resolve(1, 100, 4), Strategy::Sequential);
+        assert_eq!(Strategy::Auto.resolve(1, 1 << 30, 4), Strategy::PerBlock);
+        assert_eq!(Strategy::Auto.resolve(8, 1 << 30, 4), Strategy::PerFile);
+        assert_eq!(Strategy::PerBlock.resolve(8, 100, 4), Strategy::PerBlock);
+    }
+
+    #[test]
+    fn custom_pool() {
+        let exec = Execution::with_threads(Strategy::PerBlock, 3).unwrap();
+        assert_eq!(exec.threads(), 3);
+        assert_eq!(exec.install(rayon::current_num_threads), 3);
+    }
+}
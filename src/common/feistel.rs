@@ -1,36 +1,133 @@
 use crate::error::*;
-use crate::hasher::{hash_xor_key, pad, pad_key};
+use crate::hasher::{hash_xor_key_into, DIGEST_LEN};
+use crate::secret::Key;
+use std::cmp::min;
+
+/// The narrowest network width (in bytes) used for the keystream. This is also the width every
+/// file encrypted before the width became configurable was generated with.
+pub const DEFAULT_WIDTH: usize = 128;
+
+/// The default length (in bytes) of the round key, matching half of the default width.
+pub const DEFAULT_KEY_LEN: usize = 64;
+
+/// A feistel network with a fixed key, number of rounds and width. It owns every buffer the rounds
+/// need, so encrypting many messages with the same key (as counter block does, once for every
+/// block) does not allocate anything after the network is created.
+#[derive(Debug)]
+pub struct Network {
+    key: Key,
+    round_key: Key,
+    rounds: i32,
+    state: Vec<u8>,
+    f_in: Vec<u8>,
+    f_out: Vec<u8>,
+}
+
+impl Network {
+    /// Creates a network. The parameters are the same as the ones of encrypt.
+    pub fn new(key: &Key, rounds: i32, width: usize, key_len: usize) -> Self {
+        assert_eq!(width % 2, 0, "width should be even");
+        let key = key.padded(key_len);
+        let round_key = key.padded(key_len);
+        Network {
+            key,
+            round_key,
+            rounds,
+            state: vec![0u8; width],
+            f_in: Vec::new(),
+            f_out: vec![0u8; width / 2],
+        }
+    }
+
+    /// Encrypts msg, padded with nulls to the width of the network. The result is only valid
+    /// until the next call.
+    pub fn encrypt(&mut self, msg: &[u8]) -> &[u8] {
+        assert!(
+            msg.len() <= self.state.len(),
+            "msg should not be longer then width"
+        );
+        self.state[..msg.len()].copy_from_slice(msg);
+        self.state[msg.len()..].iter_mut().for_each(|x| *x = 0);
+        self.round_key
+            .as_mut_bytes()
+            .copy_from_slice(self.key.as_bytes());
+        for _ in 0..self.rounds {
+            round(
+                &mut self.state,
+                &self.round_key,
+                &mut self.f_in,
+                &mut self.f_out,
+            );
+            inc_key(&mut self.round_key);
+        }
+        &self.state
+    }
+
+    /// Decrypts msg, which has to be exactly as long as the width of the network. The result is
+    /// only valid until the next call.
+    pub fn decrypt(&mut self, msg: &[u8]) -> &[u8] {
+        assert!(
+            msg.len() == self.state.len(),
+            "msg should be exactly width bytes long"
+        );
+        self.state.copy_from_slice(msg);
+        swap(&mut self.state);
+        // start from the final key and decrement it for each round
+        self.round_key
+            .as_mut_bytes()
+            .copy_from_slice(self.key.as_bytes());
+        for _ in 0..self.rounds - 1 {
+            inc_key(&mut self.round_key);
+        }
+        for _ in 0..self.rounds {
+            round(
+                &mut self.state,
+                &self.round_key,
+                &mut self.f_in,
+                &mut self.f_out,
+            );
+            dec_key(&mut self.round_key);
+        }
+        swap(&mut self.state);
+        &self.state
+    }
+}
 
 /// Encrypt a vector of bytes using a fistel network.
 /// decryption is also implemented, althogh unnecessary due to the counter-block mode of operation.
+/// To encrypt many messages with the same key, create a Network once and reuse it instead.
 ///  # Parameters
 /// - msg: the byte vector that you want to encrypt
-/// - key: a byte vector. I use the user supplied password for the key.  
+/// - key: the key. I use the user supplied password for the key.  
 /// - rounds: i32, the number of fiestel rounds to preform. recomended above 3.
-pub fn encrypt(mut msg: Vec<u8>, mut key: Vec<u8>, rounds: i32) -> Result<Vec<u8>, EncryptErr> {
-    // TODO: assertions
-    pad(&mut msg, 128);
-    pad_key(&mut key, 64);
-    for _ in 0..rounds {
-        fiestel_round(&mut msg, &key)?;
-        inc_key(&mut key);
-    }
-    Ok(msg)
+/// - width: the size (in bytes) of the network. msg is padded to this size, and must not be
+///   longer then it. has to be even, since the network splits it to two halves.
+/// - key_len: the size (in bytes) the key is padded / truncated to before the first round.
+pub fn encrypt(
+    msg: Vec<u8>,
+    key: &Key,
+    rounds: i32,
+    width: usize,
+    key_len: usize,
+) -> Result<Vec<u8>, EncryptErr> {
+    Ok(Network::new(key, rounds, width, key_len)
+        .encrypt(&msg)
+        .to_vec())
 }
 
 /// Decrypt a vector of bytes using a fiestel network.
 /// This function is not used by the crate, because of the counter block mode-of-operation.
-pub fn decrypt(mut msg: Vec<u8>, mut key: Vec<u8>, rounds: i32) -> Result<Vec<u8>, DecryptErr> {
-    // TODO: assertions
-    pad_key(&mut key, 64);
-    swap(&mut msg);
-    let mut final_key = calc_final_key(&key, rounds - 1);
-    for _ in 0..rounds {
-        fiestel_round(&mut msg, &final_key)?;
-        dec_key(&mut final_key);
-    }
-    swap(&mut msg);
-    Ok(msg)
+/// width and key_len must be the same as the ones used for encryption.
+pub fn decrypt(
+    msg: Vec<u8>,
+    key: &Key,
+    rounds: i32,
+    width: usize,
+    key_len: usize,
+) -> Result<Vec<u8>, DecryptErr> {
+    Ok(Network::new(key, rounds, width, key_len)
+        .decrypt(&msg)
+        .to_vec())
 }
 
 /// preform a single fiestel round:
@@ -44,64 +141,78 @@ pub fn decrypt(mut msg: Vec<u8>, mut key: Vec<u8>, rounds: i32) -> Result<Vec<u8
 ///                  |
 /// [------right-----|--left ^ f(right)--]
 ///  
-pub fn fiestel_round(msg: &mut Vec<u8>, k: &[u8]) -> Result<(), EncryptErr> {
-    assert!(msg.len() == 128, "msg should be 2X256bits / 128 bytes");
-    assert!(k.len() == 64, "key should be 256bits / 64 bytes");
-
-    let mut right = msg.split_off(msg.len() / 2);
-    let f_of_right = f_func(&mut right.clone(), k)?;
-    msg.iter_mut()
-        .zip(f_of_right.iter())
-        .for_each(|(x1, x2)| *x1 ^= *x2);
-    right.append(msg);
-    *msg = right;
+pub fn fiestel_round(msg: &mut [u8], k: &Key) -> Result<(), EncryptErr> {
+    let mut f_out = vec![0u8; msg.len() / 2];
+    round(msg, k, &mut Vec::new(), &mut f_out);
     Ok(())
 }
 
-/// the irreversibel function used by the fiestel network. In this case I implemented a simple xor
-/// with a key.
-fn f_func(v: &mut Vec<u8>, k: &[u8]) -> Result<Vec<u8>, EncryptErr> {
-    hash_xor_key(v, &mut k.to_owned())
+/// preforms a fiestel round on msg in place. f_in and f_out are the buffers used for computing
+/// f(right), f_out has to be half the length of msg.
+fn round(msg: &mut [u8], k: &Key, f_in: &mut Vec<u8>, f_out: &mut [u8]) {
+    assert_eq!(msg.len() % 2, 0, "msg should be of even length");
+    assert!(!k.is_empty(), "key should not be empty");
+
+    let half = msg.len() / 2;
+    f_func(&msg[half..], k, f_in, f_out);
+    msg[..half]
+        .iter_mut()
+        .zip(f_out.iter())
+        .for_each(|(x1, x2)| *x1 ^= *x2);
+    msg.rotate_left(half);
 }
 
-fn inc_key(k: &mut Vec<u8>) {
-    k.iter_mut().for_each(|x| *x += 1);
+/// the irreversibel function used by the fiestel network. In this case I implemented a simple xor
+/// with a key.
+/// The output fills out: a single hash covers 64 bytes, so wider halves are filled with the
+/// hashes of the first hash followed by a block counter, instead of repeating the first hash.
+/// Only the first hash covers all of v, so the cost of f grows linearly with the width.
+fn f_func(v: &[u8], k: &Key, f_in: &mut Vec<u8>, out: &mut [u8]) {
+    let (first, rest) = out.split_at_mut(min(DIGEST_LEN, out.len()));
+    f_in.clear();
+    f_in.extend_from_slice(v);
+    hash_xor_key_into(f_in, k, first);
+    for (counter, chunk) in (1u32..).zip(rest.chunks_mut(DIGEST_LEN)) {
+        f_in.clear();
+        f_in.extend_from_slice(first);
+        f_in.extend_from_slice(&counter.to_le_bytes());
+        hash_xor_key_into(f_in, k, chunk);
+    }
 }
 
-fn dec_key(k: &mut Vec<u8>) {
-    k.iter_mut().for_each(|x| *x -= 1);
+fn inc_key(k: &mut Key) {
+    k.as_mut_bytes()
+        .iter_mut()
+        .for_each(|x| *x = x.wrapping_add(1));
 }
 
-/// calculate the final key, to be used in decryption (where we start from the final key and
-/// decrement it for each round)
-fn calc_final_key(k: &[u8], rounds: i32) -> Vec<u8> {
-    let mut final_key = k.to_owned();
-    for _ in 0..rounds {
-        inc_key(&mut final_key)
-    }
-    final_key
+fn dec_key(k: &mut Key) {
+    k.as_mut_bytes()
+        .iter_mut()
+        .for_each(|x| *x = x.wrapping_sub(1));
 }
 
 /// Swap the left and right parts of the msg.
-pub fn swap(msg: &mut Vec<u8>) {
-    let mut s = msg.split_off(msg.len() / 2);
-    s.append(msg);
-    *msg = s;
+pub fn swap(msg: &mut [u8]) {
+    let half = msg.len() / 2;
+    msg.rotate_left(half);
 }
 
 #[cfg(test)]
 mod tests {
     use crate::feistel;
+    use crate::secret::Key;
 
     // => encryption with pre-comuted values
     // => decryption with pre-comuted values
     // => encryption with short msg, long key
+    // => round trip of a network wider then the default
     #[test]
     fn enc_bytes() {
         let msg = String::from("hello world, this is my string! it may contain אותיות בעברית")
             .into_bytes();
-        let key = String::from("super_secret123!@#").into_bytes();
-        let res = feistel::encrypt(msg, key, 5).unwrap();
+        let key = Key::from("super_secret123!@#");
+        let res = feistel::encrypt(msg, &key, 5, 128, 64).unwrap();
 
         assert_eq!(
             res,
@@ -127,8 +238,8 @@ mod tests {
             5, 88, 66, 79, 89, 76, 77, 90, 87, 66, 65, 14, 13, 2, 16, 81, 11, 91, 17, 187, 244,
             224, 173, 183, 199, 224, 252, 187, 166, 232, 204, 71, 224, 245, 229, 145,
         ];
-        let key = String::from("super_secret123!@#").into_bytes();
-        let res = feistel::decrypt(bytes, key, 5).unwrap();
+        let key = Key::from("super_secret123!@#");
+        let res = feistel::decrypt(bytes, &key, 5, 128, 64).unwrap();
         assert_eq!(
             String::from_utf8(res).unwrap().replace("\u{0}", ""),
             String::from("hello world, this is my string! it may contain אותיות בעברית")
@@ -138,8 +249,8 @@ mod tests {
     #[test]
     fn enc_short_msg_long_key() {
         let msg = String::from("hey").into_bytes();
-        let key = String::from("super_secret123!@#blabalbalbalbalablabal123123123").into_bytes();
-        let res = feistel::encrypt(msg, key, 5).unwrap();
+        let key = Key::from("super_secret123!@#blabalbalbalbalablabal123123123");
+        let res = feistel::encrypt(msg, &key, 5, 128, 64).unwrap();
         assert_eq!(
             res,
             vec![
@@ -152,4 +263,20 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn enc_dec_wide() {
+        let msg = String::from("hello world, this is my string! it may contain אותיות בעברית")
+            .into_bytes();
+        let key = Key::from("super_secret123!@#");
+        let enc = feistel::encrypt(msg, &key, 5, 512, 64).unwrap();
+        assert_eq!(enc.len(), 512);
+        assert_ne!(enc[..128], enc[128..256]);
+
+        let dec = feistel::decrypt(enc, &key, 5, 512, 64).unwrap();
+        assert_eq!(
+            String::from_utf8(dec).unwrap().replace("\u{0}", ""),
+            String::from("hello world, this is my string! it may contain אותיות בעברית")
+        );
+    }
 }
@@ -1,12 +1,23 @@
 use crate::counter_block;
 use crate::error::*;
-use byteorder::{LittleEndian, WriteBytesExt};
+use crate::header::EncHeader;
+use crate::progress::Progress;
+use crate::secret::Key;
 use glob::{glob_with, MatchOptions};
-use std::cmp::max;
-use std::fs::{metadata, File};
+use memmap2::Mmap;
+use std::cmp::min;
+use std::fs;
+use std::fs::{metadata, File, OpenOptions};
+use std::io;
 use std::io::prelude::*;
-use std::io::{Cursor, SeekFrom};
-use std::mem;
+use std::io::{ErrorKind, SeekFrom};
+use std::ops::Range;
+#[cfg(unix)]
+use std::os::unix::fs::FileExt;
+#[cfg(windows)]
+use std::os::windows::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 ///Used for reading a file for encryption
 /// # Errors
@@ -20,175 +31,390 @@ pub fn read_clear_file(path: &str) -> Result<Vec<u8>, EncryptErr> {
 }
 
 ///Used for writing a file after it has been decrypted.
+/// The file is written atomically (see AtomicFile), and an existing file is never overwritten.
 /// # Errors
-/// returns an error if there is a problem creating the file or a problem writing to it.
+/// returns an error if the file exists, or if there is a problem creating the file or a problem
+/// writing to it.
 pub fn write_clear_file(path: &str, buff: Vec<u8>) -> Result<(), DecryptErr> {
-    let mut f = File::create(path)?;
+    let mut f = AtomicFile::create(path, false)?;
     f.write_all(&buff)?;
+    f.commit()?;
     Ok(())
 }
 
+/// A file that is written to a temporary file in the directory of its path, and moved to its path
+/// only when it is complete (see commit), so a crash or an error never leaves a partly written
+/// file at path. Dropping it without commit removes the temporary file.
+#[derive(Debug)]
+pub struct AtomicFile {
+    file: File,
+    path: PathBuf,
+    tmp_path: PathBuf,
+    overwrite: bool,
+    committed: bool,
+}
+
+impl AtomicFile {
+    /// Creates the temporary file for path. Unless overwrite is true, an existing file at path is
+    /// an error, here and again in commit.
+    /// # Errors
+    /// returns an AlreadyExists error if path exists, and any error creating the temporary file.
+    pub fn create(path: &str, overwrite: bool) -> io::Result<Self> {
+        static TMP_COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = PathBuf::from(path);
+        if !overwrite && fs::symlink_metadata(&path).is_ok() {
+            return Err(already_exists(&path));
+        }
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{} is not a file name", path.display()),
+                ))
+            }
+        };
+        loop {
+            let tmp_path = path.with_file_name(format!(
+                ".{}.{}.{}.tmp",
+                name,
+                std::process::id(),
+                TMP_COUNT.fetch_add(1, Ordering::Relaxed)
+            ));
+            // create_new, so a temporary file left by a crashed run is never written over
+            match OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&tmp_path)
+            {
+                Ok(file) => {
+                    return Ok(AtomicFile {
+                        file,
+                        path,
+                        tmp_path,
+                        overwrite,
+                        committed: false,
+                    })
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// The temporary file, for positioned reads and writes (see read_at and write_at).
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Flushes the temporary file to disk and moves it to its path, so path has either its old
+    /// content (or nothing) or all of the new one, even after a crash.
+    /// # Errors
+    /// returns an AlreadyExists error if the file may not be overwritten and path was created
+    /// since create, and any error syncing or moving the file. The temporary file is removed.
+    pub fn commit(mut self) -> io::Result<()> {
+        self.file.sync_all()?;
+        if self.overwrite {
+            fs::rename(&self.tmp_path, &self.path)?;
+        } else {
+            // unlike rename, a hard link fails if path exists, so a file created since create is
+            // not clobbered either. file systems without hard links fall back to rename.
+            match fs::hard_link(&self.tmp_path, &self.path) {
+                Ok(()) => fs::remove_file(&self.tmp_path)?,
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    return Err(already_exists(&self.path))
+                }
+                Err(_) if fs::symlink_metadata(&self.path).is_ok() => {
+                    return Err(already_exists(&self.path))
+                }
+                Err(_) => fs::rename(&self.tmp_path, &self.path)?,
+            }
+        }
+        self.committed = true;
+        sync_dir(&self.path)
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.tmp_path);
+        }
+    }
+}
+
+/// Writes header over the header at the start of f, once the clear text length of a stream
+/// encrypted into f is known (see counter_block::encrypt_stream). header has to be as long as the
+/// header it replaces, which it is if only the clear text length changed.
+pub fn rewrite_header(f: &File, header: &EncHeader) -> io::Result<()> {
+    write_at(f, &header.to_bytes(), 0)
+}
+
+/// Overwrites the file at path with zeros, syncs it to disk, and removes it. returns the number of
+/// bytes overwritten.
+/// The old content is only gone from the blocks the file used: file systems that copy on write,
+/// journals, snapshots and SSDs can keep copies of it elsewhere.
+/// # Errors
+/// refuses anything but a regular file, so links are not followed to their target, and (on unix)
+/// files with more then one hard link, whose content is still in use under another name.
+pub fn shred(path: &str) -> io::Result<u64> {
+    // checked before opening, opening a fifo for writing blocks
+    shreddable(path)?;
+    let f = OpenOptions::new().write(true).open(path)?;
+    shred_open(path, f)
+}
+
+/// Like shred, but overwrites the file through f, which has to be open for writing. Callers that
+/// checked the content of the file through f this way shred that content, even if a different
+/// file was put at path since.
+/// # Errors
+/// as shred, and refuses to shred if path is no longer the file f has open.
+pub fn shred_open(path: &str, mut f: File) -> io::Result<u64> {
+    let meta = shreddable(path)?;
+    let open_meta = f.metadata()?;
+    if !same_file(&meta, &open_meta) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} was replaced while it was open", path),
+        ));
+    }
+    let len = open_meta.len();
+    let zeros = vec![0u8; min(len, 1 << 20) as usize];
+    let mut left = len;
+    f.seek(SeekFrom::Start(0))?;
+    while left > 0 {
+        let n = min(left, zeros.len() as u64) as usize;
+        f.write_all(&zeros[..n])?;
+        left -= n as u64;
+    }
+    f.sync_all()?;
+    drop(f);
+    fs::remove_file(path)?;
+    sync_dir(Path::new(path))?;
+    Ok(len)
+}
+
+/// returns the metadata of path, if it is a regular file without other hard links.
+fn shreddable(path: &str) -> io::Result<fs::Metadata> {
+    let meta = fs::symlink_metadata(path)?;
+    if !meta.file_type().is_file() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} is not a regular file", path),
+        ));
+    }
+    if hard_links(&meta) > 1 {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} has other hard links", path),
+        ));
+    }
+    Ok(meta)
+}
+
+#[cfg(unix)]
+fn hard_links(meta: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    meta.nlink()
+}
+
+#[cfg(windows)]
+fn hard_links(_meta: &fs::Metadata) -> u64 {
+    1
+}
+
+#[cfg(unix)]
+fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+#[cfg(windows)]
+fn same_file(_a: &fs::Metadata, _b: &fs::Metadata) -> bool {
+    true
+}
+
+fn already_exists(path: &Path) -> io::Error {
+    io::Error::new(
+        ErrorKind::AlreadyExists,
+        format!("{} already exists", path.display()),
+    )
+}
+
+/// Syncs the directory of path, so a file moved into it survives a crash.
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(windows)]
+fn sync_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Opens an encrypted file and reads its header, leaving the cursor at the first block.
+fn open_enc_file(path: &str) -> Result<(File, EncHeader), DecryptErr> {
+    let mut f = File::open(path)?;
+    let file_size = f.metadata()?.len();
+    let header = EncHeader::read_from(&mut f, file_size)?;
+    Ok((f, header))
+}
+
+/// Reads the blocks in range from the blocks of an encrypted file.
+fn read_blocks(
+    f: &mut File,
+    header: EncHeader,
+    range: Range<u64>,
+) -> Result<counter_block::Blocks, DecryptErr> {
+    // range is never more then the blocks in the file, so this fits in memory as much as the file
+    let len = ((range.end - range.start) * header.block_size() as u64) as usize;
+    let data = read_from_to(f, header.block_offset(range.start), len)?;
+    Ok(counter_block::Blocks {
+        version: header.version(),
+        block_size: header.block_size(),
+        f_rounds: header.f_rounds(),
+        plaintext_len: header.plaintext_len(),
+        key_check: header.check_value(),
+        nonce: header.nonce().to_vec(),
+        data,
+    })
+}
+
 ///Used for reading a file for encryption.
 /// The function parses the file costume header created by the encryption and returns a <counter_block::Blocks> struct.
 /// notice: encrypted files will probably have a different file extention then their unencrypted version!
 /// # Errors
 /// Returns error if file not found or there is a problem in reading it.
 pub fn read_enc_file(path: &str) -> Result<counter_block::Blocks, DecryptErr> {
-    // TODO: assertions
-    let mut f = File::open(path)?;
-    let mut block_size_buff = [0u8; mem::size_of::<i32>()];
-    let mut nonce_size_buff = [0u8; mem::size_of::<i32>()];
-    let mut rounds_num_buff = [0u8; mem::size_of::<i32>()];
-
-    f.read_exact(&mut block_size_buff)?;
-    f.read_exact(&mut nonce_size_buff)?;
-    f.read_exact(&mut rounds_num_buff)?;
-
-    let block_size: i32 = i32::from_le_bytes(block_size_buff);
-    let nonce_size: i32 = i32::from_le_bytes(nonce_size_buff);
-    let f_rounds: i32 = i32::from_le_bytes(rounds_num_buff);
-    let file_size = f.metadata().unwrap().len() as usize;
-    if nonce_size > file_size as i32 {
-        return Err(DecryptErr::IoError(String::from(
-            "file not encrypted or corrupted",
-        )));
-    }
-
-    let rest_of_file = file_size - (3 * mem::size_of::<i32>()) - nonce_size as usize;
-    let mut nonce = vec![0u8; nonce_size as usize];
-    f.read_exact(&mut nonce)?;
-
-    let mut blocks: Vec<Vec<u8>> = Vec::with_capacity(rest_of_file / block_size as usize);
-    for _ in 0..blocks.capacity() {
-        let mut buff = vec![0u8; block_size as usize];
-        f.read_exact(&mut buff)?;
-        blocks.push(buff);
-    }
-    Ok(counter_block::Blocks {
-        nonce,
-        f_rounds,
-        blocks,
-    })
+    let (mut f, header) = open_enc_file(path)?;
+    let range = 0..header.block_count();
+    read_blocks(&mut f, header, range)
 }
 
 ///Used for writing a <counter_block::Blocks> struct to a file.
 /// Converts the nonce, block size and other params to a header and appends the raw bytes to it.
+/// The file is written atomically (see AtomicFile), and an existing file is never overwritten.
 /// # Errors
-/// returns an error if there is a problem creating the file or a problem writing to it.
-pub fn write_blocks(mut cypher: counter_block::Blocks, path: &str) -> Result<(), EncryptErr> {
-    // TODO: assertions
-
-    let block_size = cypher.blocks[0].len();
-    let byte_size = cypher.blocks.len() * block_size;
-    let nonce_size = cypher.nonce.len();
-
-    let mut serial_rounds_num = [0u8; mem::size_of::<i32>()];
-    let mut serial_block_size = [0u8; mem::size_of::<i32>()];
-    let mut serial_nonce_size = [0u8; mem::size_of::<i32>()];
-
-    serial_rounds_num
-        .as_mut()
-        .write_i32::<LittleEndian>(cypher.f_rounds)
-        .expect("could not write num rounds");
-    serial_block_size
-        .as_mut()
-        .write_i32::<LittleEndian>(block_size as i32)
-        .expect("could not write block size");
-    serial_nonce_size
-        .as_mut()
-        .write_i32::<LittleEndian>(nonce_size as i32)
-        .expect("could not write nonce size");
-
-    let mut write_buff: Vec<u8> =
-        Vec::with_capacity(nonce_size + byte_size + serial_rounds_num.len());
-
-    write_buff.append(&mut serial_block_size.to_vec());
-    write_buff.append(&mut serial_nonce_size.to_vec());
-    write_buff.append(&mut serial_rounds_num.to_vec());
-    write_buff.append(&mut cypher.nonce);
-    for b in cypher.blocks.iter_mut() {
-        write_buff.append(b)
-    }
-    let mut enc_file = File::create(path)?;
-    enc_file.write_all(&write_buff)?;
+/// returns an error if there is a problem creating the file or a problem writing to it, or if the
+/// blocks have no key check value (they were read from a file written before it was stored).
+pub fn write_blocks(cypher: counter_block::Blocks, path: &str) -> Result<(), EncryptErr> {
+    let key_check = cypher.key_check.ok_or(EncryptErr::MissingKeyCheck)?;
+    let mut enc_file = AtomicFile::create(path, false)?;
+    let plaintext_len = cypher.plaintext_len.unwrap_or(cypher.data.len() as u64);
+    EncHeader::new(
+        cypher.nonce,
+        cypher.block_size,
+        cypher.f_rounds,
+        plaintext_len,
+        key_check,
+    )
+    .write_to(&mut enc_file)?;
+    enc_file.write_all(&cypher.data)?;
+    enc_file.commit()?;
+    Ok(())
+}
+
+/// Creates an encrypted file with header: writes the header and preallocates the blocks, so they
+/// can be written at their final offsets (see EncHeader::block_offset) with write_at, in any order
+/// and from many threads at once. The file only appears at path when it is committed, and
+/// replaces an existing file only if overwrite is true.
+pub fn create_enc_file(
+    path: &str,
+    header: &EncHeader,
+    overwrite: bool,
+) -> Result<AtomicFile, EncryptErr> {
+    let data_len = header
+        .block_count()
+        .checked_mul(header.block_size() as u64)
+        .ok_or(EncryptErr::CounterOverflow)?;
+    let mut enc_file = AtomicFile::create(path, overwrite)?;
+    header.write_to(&mut enc_file)?;
+    enc_file.file().set_len(header.data_start() + data_len)?;
+    Ok(enc_file)
+}
+
+/// Reads exactly buff.len() bytes from offset, without moving the cursor of the file, so many
+/// threads can read from the same file at once.
+#[cfg(unix)]
+pub fn read_at(f: &File, buff: &mut [u8], offset: u64) -> std::io::Result<()> {
+    f.read_exact_at(buff, offset)
+}
+
+/// Writes all of buff at offset, without moving the cursor of the file, so many threads can write
+/// to the same file at once.
+#[cfg(unix)]
+pub fn write_at(f: &File, buff: &[u8], offset: u64) -> std::io::Result<()> {
+    f.write_all_at(buff, offset)
+}
+
+#[cfg(windows)]
+pub fn read_at(f: &File, mut buff: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    while !buff.is_empty() {
+        match f.seek_read(buff, offset)? {
+            0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            n => {
+                buff = &mut buff[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+pub fn write_at(f: &File, mut buff: &[u8], mut offset: u64) -> std::io::Result<()> {
+    while !buff.is_empty() {
+        let n = f.seek_write(buff, offset)?;
+        buff = &buff[n..];
+        offset += n as u64;
+    }
     Ok(())
 }
 
-// TODO: load head / tail of file
 /// Read the first n blocks of an encrypted file. Due to the nature of counter block, we can
 /// decrypt any block of the encrypted file without decrypting the rest. This is used by the crate to grep over
 /// parts of encrypted files faster.
-pub fn read_first_n(path: &str, n: i32) -> Result<counter_block::Blocks, DecryptErr> {
-    let mut f = File::open(path)?;
-    let mut block_size_buff = [0u8; mem::size_of::<i32>()];
-    let mut nonce_size_buff = [0u8; mem::size_of::<i32>()];
-    let mut rounds_num_buff = [0u8; mem::size_of::<i32>()];
-
-    f.read_exact(&mut block_size_buff)?;
-    f.read_exact(&mut nonce_size_buff)?;
-    f.read_exact(&mut rounds_num_buff)?;
-
-    let block_size: i32 = i32::from_le_bytes(block_size_buff);
-    let nonce_size: i32 = i32::from_le_bytes(nonce_size_buff);
-    let f_rounds: i32 = i32::from_le_bytes(rounds_num_buff);
-    let start_of_blocks = (3 * mem::size_of::<i32>()) as u64 + nonce_size as u64;
-    let len = n as usize * block_size as usize;
-
-    let mut nonce = vec![0u8; nonce_size as usize];
-    f.read_exact(&mut nonce)?;
-
-    let blocks_vec: Vec<u8> = read_from_to(&mut f, start_of_blocks, len)?;
-    let blocks: Vec<Vec<u8>> = blocks_vec
-        .chunks_exact(block_size as usize)
-        .map(|x| x.to_vec())
-        .collect();
-    Ok(counter_block::Blocks {
-        nonce,
-        f_rounds,
-        blocks,
-    })
+/// If the file has less then n blocks, all of them are read.
+pub fn read_first_n(path: &str, n: u64) -> Result<counter_block::Blocks, DecryptErr> {
+    let (mut f, header) = open_enc_file(path)?;
+    let range = header.head(n);
+    read_blocks(&mut f, header, range)
 }
 
-/// read the last n blocks of a file
-pub fn read_last_n(path: &str, n: i32) -> Result<(counter_block::Blocks, i64), DecryptErr> {
-    let mut f = File::open(path)?;
-    let mut block_size_buff = [0u8; mem::size_of::<i32>()];
-    let mut nonce_size_buff = [0u8; mem::size_of::<i32>()];
-    let mut rounds_num_buff = [0u8; mem::size_of::<i32>()];
-
-    f.read_exact(&mut block_size_buff)?;
-    f.read_exact(&mut nonce_size_buff)?;
-    f.read_exact(&mut rounds_num_buff)?;
-
-    let block_size: i32 = i32::from_le_bytes(block_size_buff);
-    let nonce_size: i32 = i32::from_le_bytes(nonce_size_buff);
-    let f_rounds: i32 = i32::from_le_bytes(rounds_num_buff);
-    let start_of_blocks = (3 * mem::size_of::<i32>()) as u64 + nonce_size as u64;
-    let len = n as usize * block_size as usize;
-    let file_size = f.metadata().unwrap().len();
-    let mut nonce = vec![0u8; nonce_size as usize];
-    f.read_exact(&mut nonce)?;
-
-    let start = max(start_of_blocks, file_size - len as u64);
-    let block_num = (start - start_of_blocks) as i64 / block_size as i64;
-    let blocks_vec: Vec<u8> = read_from_to(&mut f, start, len)?;
-    let blocks: Vec<Vec<u8>> = blocks_vec
-        .chunks_exact(block_size as usize)
-        .map(|x| x.to_vec())
-        .collect();
-    Ok((
-        counter_block::Blocks {
-            nonce,
-            f_rounds,
-            blocks,
-        },
-        block_num,
-    ))
+/// read the last n blocks of a file, and the index of the first of them.
+/// If the file has less then n blocks, all of them are read.
+pub fn read_last_n(path: &str, n: u64) -> Result<(counter_block::Blocks, u64), DecryptErr> {
+    let (mut f, header) = open_enc_file(path)?;
+    let range = header.tail(n);
+    let first = range.start;
+    Ok((read_blocks(&mut f, header, range)?, first))
 }
 
 /// Read a number of bytes from the middle of a file.
+/// # Errors
+/// returns an UnexpectedEof error if the file ends before from + len.
 pub fn read_from_to(f: &mut File, from: u64, len: usize) -> std::io::Result<Vec<u8>> {
-    assert!(f.metadata().unwrap().len() >= from + len as u64);
+    let file_size = f.metadata()?.len();
+    if from
+        .checked_add(len as u64)
+        .map_or(true, |end| end > file_size)
+    {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
 
     let mut buff: Vec<u8> = vec![0u8; len];
     f.seek(SeekFrom::Start(from))?;
@@ -196,6 +422,105 @@ pub fn read_from_to(f: &mut File, from: u64, len: usize) -> std::io::Result<Vec<
     Ok(buff)
 }
 
+/// An encrypted file mapped into memory. The header is parsed once when the file is opened, and
+/// blocks are slices of the mapping, so reading any range of blocks makes no syscalls and no
+/// copies. This is the reader to use for reading parts of many files, as brgrep does.
+#[derive(Debug)]
+pub struct MappedEncFile {
+    map: Mmap,
+    header: EncHeader,
+}
+
+impl MappedEncFile {
+    /// Maps the encrypted file at path and parses its header.
+    /// # Errors
+    /// returns an error if the file can not be opened or mapped, or if its header does not fit
+    /// the file.
+    pub fn open(path: &str) -> Result<Self, DecryptErr> {
+        let f = File::open(path)?;
+        let file_size = f.metadata()?.len();
+        // the mapping is only valid as long as the file is not truncated. encrypted files are
+        // written once and never modified in place.
+        let map = unsafe { Mmap::map(&f)? };
+        let header = EncHeader::read_from(&mut &map[..], file_size)?;
+        Ok(MappedEncFile { map, header })
+    }
+
+    /// The header of the file.
+    pub fn header(&self) -> &EncHeader {
+        &self.header
+    }
+
+    /// The nonce of the file.
+    pub fn nonce(&self) -> &[u8] {
+        self.header.nonce()
+    }
+
+    /// The size of every block in the file.
+    pub fn block_size(&self) -> usize {
+        self.header.block_size()
+    }
+
+    /// The number of fiestel rounds the file was encrypted with.
+    pub fn f_rounds(&self) -> i32 {
+        self.header.f_rounds()
+    }
+
+    /// The length of the clear text, or None for files written before it was stored.
+    pub fn plaintext_len(&self) -> Option<u64> {
+        self.header.plaintext_len()
+    }
+
+    /// The number of blocks in the file. The file is mapped, so they fit in a usize.
+    pub fn block_count(&self) -> usize {
+        self.header.block_count() as usize
+    }
+
+    /// The encrypted blocks in range, as one slice. Returns None if range is out of bounds.
+    pub fn blocks(&self, range: Range<usize>) -> Option<&[u8]> {
+        if range.start > range.end || range.end > self.block_count() {
+            return None;
+        }
+        let start = self.header.block_offset(range.start as u64) as usize;
+        let end = self.header.block_offset(range.end as u64) as usize;
+        Some(&self.map[start..end])
+    }
+
+    /// The encrypted block at index. Returns None if index is out of bounds.
+    pub fn block(&self, index: usize) -> Option<&[u8]> {
+        self.blocks(index..index + 1)
+    }
+
+    /// Decrypts the blocks in range in parallel. The range is clamped to the blocks of the file,
+    /// so head and tail reads can ask for more blocks then there are. The padding of the last
+    /// block is not returned, if the file records the length of the clear text. progress is
+    /// called as the blocks are decrypted.
+    pub fn decrypt(
+        &self,
+        key: &Key,
+        range: Range<usize>,
+        progress: &Progress,
+    ) -> Result<Vec<u8>, DecryptErr> {
+        let end = min(range.end, self.block_count());
+        let start = min(range.start, end);
+        let mut clear = counter_block::par_decrypt_slice(
+            &self.header,
+            self.blocks(start..end).unwrap(),
+            key,
+            start as u64,
+            progress,
+        )?;
+        let len = counter_block::clear_len(
+            self.plaintext_len(),
+            self.block_size(),
+            start as u64,
+            clear.len(),
+        );
+        clear.truncate(len);
+        Ok(clear)
+    }
+}
+
 /// Expands a path with glob notation to a vector of file paths.
 pub fn list_glob(
     path: &str,
@@ -203,10 +528,402 @@ pub fn list_glob(
 ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
     let paths = glob_with(path, options)?;
     let mut res: Vec<String> = Vec::new();
-    for entry in paths {
-        if let Ok(p) = entry {
-            res.push(p.display().to_string());
-        }
+    for p in paths.flatten() {
+        res.push(p.display().to_string());
     }
     Ok(res)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counter_block::Blocks;
+    use crate::nonce::{OsNonce, SeededNonce};
+    use crate::progress;
+    use crate::test_util::{key, temp_path, testdata};
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+    use std::env;
+    use std::fs;
+
+    fn golden_enc(block_size: usize, name: &str) {
+        let key = key();
+        let nonce = SeededNonce::new(42);
+        let blocks = Blocks::from_clear_file(
+            &testdata("clear.txt"),
+            &key,
+            block_size,
+            5,
+            &nonce,
+            &progress::ignore,
+        )
+        .unwrap();
+        let out = temp_path(name);
+        blocks.into_enc_file(&out).unwrap();
+        let res = fs::read(&out).unwrap();
+        fs::remove_file(&out).unwrap();
+
+        if env::var_os("BROKEN_BLESS").is_some() {
+            fs::write(testdata(name), &res).unwrap();
+        }
+        assert_eq!(res, fs::read(testdata(name)).unwrap());
+    }
+
+    #[test]
+    fn golden_enc_narrow_blocks() {
+        golden_enc(30, "golden_30.enc");
+    }
+
+    #[test]
+    fn golden_enc_wide_blocks() {
+        golden_enc(301, "golden_301.enc");
+    }
+
+    #[test]
+    fn golden_enc_positioned_writes() {
+        let key = key();
+        for &(block_size, name) in [(30, "golden_30.enc"), (301, "golden_301.enc")].iter() {
+            let out = temp_path(&format!("positioned_{}", name));
+            // two blocks per chunk, so the file is written by many chunks out of order
+            counter_block::encrypt_file_chunked(
+                &testdata("clear.txt"),
+                &out,
+                &key,
+                block_size,
+                5,
+                &SeededNonce::new(42),
+                counter_block::Chunking {
+                    blocks: 2,
+                    parallel: true,
+                    overwrite: false,
+                    progress: &progress::ignore_files,
+                    cancel: None,
+                },
+            )
+            .unwrap();
+            let res = fs::read(&out).unwrap();
+            fs::remove_file(&out).unwrap();
+            assert_eq!(res, fs::read(testdata(name)).unwrap());
+        }
+    }
+
+    #[test]
+    fn golden_dec() {
+        let key = key();
+        let clear = fs::read(testdata("clear.txt")).unwrap();
+        // legacy_30.enc has the unversioned header, with i32 sizes and without the clear text
+        // length, so it keeps the padding of the last block. legacy_301.enc also has the
+        // keystream of wide blocks from before the network width followed the block size.
+        for name in &[
+            "golden_30.enc",
+            "golden_301.enc",
+            "legacy_30.enc",
+            "legacy_301.enc",
+        ] {
+            let mapped = MappedEncFile::open(&testdata(name)).unwrap();
+            let blocks = read_enc_file(&testdata(name)).unwrap();
+            let dec = blocks.into_clear(&key, 0).unwrap();
+            assert_eq!(dec[..clear.len()], clear[..]);
+            assert!(dec[clear.len()..].iter().all(|b| *b == 0));
+            if !name.starts_with("legacy") {
+                assert_eq!(dec.len(), clear.len());
+            }
+            assert_eq!(
+                mapped
+                    .decrypt(&key, 0..mapped.block_count(), &progress::ignore)
+                    .unwrap(),
+                dec
+            );
+        }
+    }
+
+    #[test]
+    fn empty_file() {
+        let key = key();
+        let clear = temp_path("empty");
+        let enc = temp_path("empty.enc");
+        fs::write(&clear, b"").unwrap();
+
+        let blocks =
+            Blocks::from_clear_file(&clear, &key, 30, 5, &OsNonce, &progress::ignore).unwrap();
+        assert_eq!(blocks.block_count(), 0);
+        blocks.into_enc_file(&enc).unwrap();
+        let enc_len = fs::metadata(&enc).unwrap().len();
+        let dec = read_enc_file(&enc).unwrap().into_clear(&key, 0).unwrap();
+        let head = read_first_n(&enc, 3).unwrap().into_clear(&key, 0).unwrap();
+        let (tail, block_num) = read_last_n(&enc, 3).unwrap();
+        let tail = tail.into_clear(&key, block_num).unwrap();
+        let mapped = MappedEncFile::open(&enc).unwrap();
+        let mapped_dec = mapped.decrypt(&key, 0..3, &progress::ignore).unwrap();
+        fs::remove_file(&enc).unwrap();
+        counter_block::encrypt_file(&clear, &enc, &key, 30, 5, &OsNonce).unwrap();
+        let streamed_len = fs::metadata(&enc).unwrap().len();
+        fs::remove_file(&clear).unwrap();
+        fs::remove_file(&enc).unwrap();
+
+        // just the header: 68 bytes and the nonce
+        assert_eq!(enc_len, 188);
+        assert_eq!(streamed_len, 188);
+        assert_eq!(mapped.plaintext_len(), Some(0));
+        assert!(dec.is_empty() && head.is_empty() && tail.is_empty() && mapped_dec.is_empty());
+    }
+
+    #[test]
+    fn atomic_writes() {
+        let path = temp_path("atomic");
+        let dir = env::temp_dir();
+        let tmp_files = || {
+            fs::read_dir(&dir)
+                .unwrap()
+                .filter(|e| {
+                    let name = e.as_ref().unwrap().file_name();
+                    let name = name.to_string_lossy();
+                    name.starts_with(&format!(".broken_{}_atomic.", std::process::id()))
+                })
+                .count()
+        };
+        // nothing appears until commit, and a dropped file leaves nothing behind
+        let mut f = AtomicFile::create(&path, false).unwrap();
+        f.write_all(b"first").unwrap();
+        assert!(fs::metadata(&path).is_err());
+        drop(f);
+        assert!(fs::metadata(&path).is_err());
+        assert_eq!(tmp_files(), 0);
+
+        let mut f = AtomicFile::create(&path, false).unwrap();
+        f.write_all(b"first").unwrap();
+        f.commit().unwrap();
+        let refused = AtomicFile::create(&path, false).map(|_| ());
+        // created after the check, the file is still not clobbered
+        let mut late = AtomicFile::create(&temp_path("atomic_late"), false).unwrap();
+        fs::write(temp_path("atomic_late"), b"other").unwrap();
+        late.write_all(b"late").unwrap();
+        let late_res = late.commit();
+        let late_content = fs::read(temp_path("atomic_late")).unwrap();
+        let mut f = AtomicFile::create(&path, true).unwrap();
+        f.write_all(b"second").unwrap();
+        f.commit().unwrap();
+        let content = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(temp_path("atomic_late")).unwrap();
+
+        assert_eq!(refused.unwrap_err().kind(), ErrorKind::AlreadyExists);
+        assert_eq!(late_res.unwrap_err().kind(), ErrorKind::AlreadyExists);
+        assert_eq!(late_content, b"other");
+        assert_eq!(content, b"second");
+        assert_eq!(tmp_files(), 0);
+    }
+
+    #[test]
+    fn shred_file() {
+        let path = temp_path("shred");
+        fs::write(&path, vec![7u8; 3 << 20]).unwrap();
+        let shredded = shred(&path).unwrap();
+        let gone = fs::metadata(&path).is_err();
+        let link = temp_path("shred_link");
+        fs::write(&path, b"linked").unwrap();
+        fs::hard_link(&path, &link).unwrap();
+        let linked = shred(&path);
+        let kept = fs::read(&link).unwrap();
+        fs::remove_file(&path).unwrap();
+        // f has the file left at link open, and another file is put at path
+        let f = OpenOptions::new().write(true).open(&link).unwrap();
+        fs::write(&path, b"replaced").unwrap();
+        let replaced = shred_open(&path, f);
+        let replacement = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&link).unwrap();
+
+        assert_eq!(shredded, 3 << 20);
+        assert!(gone);
+        assert_eq!(linked.unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(kept, b"linked");
+        assert_eq!(replaced.unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert_eq!(replacement, b"replaced");
+    }
+
+    #[test]
+    fn golden_head_tail() {
+        let path = testdata("golden_30.enc");
+        let all = read_enc_file(&path).unwrap();
+        let head = read_first_n(&path, 3).unwrap();
+        let (tail, block_num) = read_last_n(&path, 3).unwrap();
+
+        assert_eq!(head.nonce, all.nonce);
+        assert_eq!(head.data[..], all.data[..3 * 30]);
+        assert_eq!(block_num as usize, all.block_count() - 3);
+        assert_eq!(tail.data[..], all.data[all.data.len() - 3 * 30..]);
+    }
+
+    #[test]
+    fn mapped_matches_readers() {
+        let path = testdata("golden_30.enc");
+        let key = key();
+        let all = read_enc_file(&path).unwrap();
+        let mapped = MappedEncFile::open(&path).unwrap();
+
+        assert_eq!(mapped.nonce(), &all.nonce[..]);
+        assert_eq!(mapped.block_count(), all.block_count());
+        assert_eq!(
+            mapped.blocks(0..mapped.block_count()).unwrap(),
+            &all.data[..]
+        );
+        assert_eq!(mapped.block(2).unwrap(), &all.data[2 * 30..3 * 30]);
+        assert!(mapped.block(mapped.block_count()).is_none());
+
+        let (tail, block_num) = read_last_n(&path, 3).unwrap();
+        let count = mapped.block_count();
+        assert_eq!(
+            mapped
+                .decrypt(&key, count - 3..count, &progress::ignore)
+                .unwrap(),
+            tail.into_clear(&key, block_num).unwrap()
+        );
+        assert_eq!(
+            mapped
+                .decrypt(&key, 0..count + 100, &progress::ignore)
+                .unwrap(),
+            all.into_clear(&key, 0).unwrap()
+        );
+    }
+
+    /// Writes bytes to a temporary file and reads it with every reader. None of them may panic,
+    /// and they all have to agree on whether the file is valid.
+    fn read_all_ways(bytes: &[u8], name: &str) -> Result<(), DecryptErr> {
+        let path = temp_path(name);
+        fs::write(&path, bytes).unwrap();
+        let key = key();
+        let all = read_enc_file(&path).and_then(|b| b.into_clear(&key, 0));
+        let head = read_first_n(&path, 3);
+        let tail = read_last_n(&path, u64::MAX);
+        let mapped = MappedEncFile::open(&path)
+            .and_then(|m| m.decrypt(&key, 0..m.block_count(), &progress::ignore));
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(head.is_ok(), all.is_ok());
+        assert_eq!(tail.is_ok(), all.is_ok());
+        assert_eq!(mapped.is_ok(), all.is_ok());
+        all.map(|_| ())
+    }
+
+    #[test]
+    fn truncated_files() {
+        // the header of golden_30.enc is 68 bytes and a 120 byte nonce, legacy_30.enc has 12
+        for &(name, header_len) in [("golden_30.enc", 188), ("legacy_30.enc", 132)].iter() {
+            let golden = fs::read(testdata(name)).unwrap();
+            for len in 0..header_len {
+                let res = read_all_ways(&golden[..len], "truncated");
+                assert!(matches!(res, Err(DecryptErr::TruncatedHeader)), "{:?}", res);
+            }
+            for len in header_len + 1..header_len + 30 {
+                let res = read_all_ways(&golden[..len], "truncated");
+                assert!(matches!(res, Err(DecryptErr::PartialBlock)), "{:?}", res);
+            }
+            // cutting whole blocks is only noticed if the header has the clear text length
+            for len in (header_len..golden.len()).step_by(30) {
+                let res = read_all_ways(&golden[..len], "truncated");
+                if header_len == 188 {
+                    assert!(
+                        matches!(res, Err(DecryptErr::LengthMismatch(431))),
+                        "{:?}",
+                        res
+                    );
+                } else {
+                    assert!(res.is_ok(), "{:?}", res);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn corrupted_headers() {
+        let golden = fs::read(testdata("golden_30.enc")).unwrap();
+        let legacy = fs::read(testdata("legacy_30.enc")).unwrap();
+        let corrupt = |file: &[u8], offset: usize, field: &[u8]| {
+            let mut bytes = file.to_vec();
+            bytes[offset..offset + field.len()].copy_from_slice(field);
+            read_all_ways(&bytes, "corrupted").unwrap_err()
+        };
+
+        let res = corrupt(&golden, 4, &2u32.to_le_bytes());
+        assert!(
+            matches!(res, DecryptErr::UnsupportedVersion(2)),
+            "{:?}",
+            res
+        );
+        let res = corrupt(&golden, 8, &0u64.to_le_bytes());
+        assert!(matches!(res, DecryptErr::InvalidBlockSize(0)), "{:?}", res);
+        let res = corrupt(&golden, 8, &u64::MAX.to_le_bytes());
+        assert!(matches!(res, DecryptErr::InvalidBlockSize(_)), "{:?}", res);
+        let res = corrupt(&golden, 8, &(1u64 << 21).to_le_bytes());
+        assert!(matches!(res, DecryptErr::InvalidBlockSize(_)), "{:?}", res);
+        let res = corrupt(&golden, 16, &u64::MAX.to_le_bytes());
+        assert!(matches!(res, DecryptErr::InvalidNonceSize(_)), "{:?}", res);
+        // a 121 byte nonce and the counter do not fit the 128 byte network of 30 byte blocks
+        let res = corrupt(&golden, 16, &121u64.to_le_bytes());
+        assert!(
+            matches!(res, DecryptErr::InvalidNonceSize(121)),
+            "{:?}",
+            res
+        );
+        let res = corrupt(&golden, 24, &0i32.to_le_bytes());
+        assert!(matches!(res, DecryptErr::InvalidRounds(0)), "{:?}", res);
+        let res = corrupt(&golden, 24, &3i32.to_le_bytes());
+        assert!(matches!(res, DecryptErr::InvalidRounds(3)), "{:?}", res);
+        let res = corrupt(&golden, 24, &i32::MAX.to_le_bytes());
+        assert!(matches!(res, DecryptErr::InvalidRounds(_)), "{:?}", res);
+        // 15 blocks of 30 bytes hold 421 to 450 bytes
+        for &len in [0u64, 420, 451, u64::MAX].iter() {
+            let res = corrupt(&golden, 28, &len.to_le_bytes());
+            assert!(matches!(res, DecryptErr::LengthMismatch(_)), "{:?}", res);
+        }
+
+        let res = corrupt(&legacy, 0, &(-30i32).to_le_bytes());
+        assert!(
+            matches!(res, DecryptErr::InvalidBlockSize(-30)),
+            "{:?}",
+            res
+        );
+        let res = corrupt(&legacy, 4, &(-1i32).to_le_bytes());
+        assert!(matches!(res, DecryptErr::InvalidNonceSize(-1)), "{:?}", res);
+        let res = corrupt(&legacy, 8, &(-5i32).to_le_bytes());
+        assert!(matches!(res, DecryptErr::InvalidRounds(-5)), "{:?}", res);
+    }
+
+    #[test]
+    fn fuzzed_headers_do_not_panic() {
+        let mut rng = ChaCha20Rng::seed_from_u64(36);
+        for name in &["golden_30.enc", "legacy_30.enc"] {
+            let golden = fs::read(testdata(name)).unwrap();
+            for _ in 0..200 {
+                let mut bytes = golden.clone();
+                for _ in 0..rng.gen_range(1, 4) {
+                    let i = rng.gen_range(0, 190);
+                    bytes[i] = rng.gen();
+                }
+                if rng.gen() {
+                    bytes.truncate(rng.gen_range(0, golden.len()));
+                }
+                let _ = read_all_ways(&bytes, "fuzzed");
+            }
+        }
+    }
+
+    #[test]
+    fn mapped_rejects_short_file() {
+        let path = temp_path("short.enc");
+        fs::write(&path, [1u8, 0, 0]).unwrap();
+        let res = MappedEncFile::open(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(res, Err(DecryptErr::TruncatedHeader)));
+    }
+
+    #[test]
+    fn legacy_blocks_are_not_written() {
+        let path = temp_path("legacy_copy.enc");
+        let blocks = read_enc_file(&testdata("legacy_30.enc")).unwrap();
+        let res = write_blocks(blocks, &path);
+        assert!(matches!(res, Err(EncryptErr::MissingKeyCheck)), "{:?}", res);
+        assert!(!Path::new(&path).exists());
+    }
+}
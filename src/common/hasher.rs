@@ -1,49 +1,60 @@
 use crate::error::EncryptErr;
-use crypto_hash::{hex_digest, Algorithm};
+use crate::secret::Key;
+use crypto_hash::{digest, Algorithm};
+use zeroize::Zeroize;
+
+/// The length of the result of hash_xor_key: a SHA256 digest, hex encoded.
+pub const DIGEST_LEN: usize = 64;
+
+const HEX_CHARS: &[u8; 16] = b"0123456789abcdef";
 
 /// pads the key and msg to the same length, xors them and then preformes a SHA256 hash on the result.
+/// The key is repeated in place of padding it, so no copy of it is made. msg holds key material
+/// after the xor, and is wiped before returning.
 /// # Examples
 /// ```rust
 /// use common::hasher::hash_xor_key;
+/// use common::secret::Key;
 /// let mut msg = String::from("hello world!").into_bytes();
-/// let mut key = String::from("super_secret").into_bytes();
-/// let res = hash_xor_key(&mut msg, &mut key).unwrap();
-
+/// let key = Key::from("super_secret");
+/// let res = hash_xor_key(&mut msg, &key).unwrap();
 /// ```
-pub fn hash_xor_key(msg: &mut Vec<u8>, key: &mut Vec<u8>) -> Result<Vec<u8>, EncryptErr> {
-    assert!(!msg.is_empty(), "msg vector is of empty!");
-    assert!(!key.is_empty(), "key vector is of empty!");
-    while msg.len() > key.len() {
-        let mut key_copy = key.clone();
-        key.append(&mut key_copy);
-    }
-    if msg.len() < key.len() {
-        pad(msg, key.len());
-    }
-    xor_key(msg, key)?;
-    Ok(hex_digest(Algorithm::SHA256, msg).into_bytes())
+pub fn hash_xor_key(msg: &mut Vec<u8>, key: &Key) -> Result<Vec<u8>, EncryptErr> {
+    let mut out = vec![0u8; DIGEST_LEN];
+    hash_xor_key_into(msg, key, &mut out);
+    Ok(out)
 }
 
-pub fn pad(v: &mut Vec<u8>, l: usize) {
-    for _ in 0..l - v.len() {
-        v.push(b'\x00');
+/// Same as hash_xor_key, but writes the first out.len() bytes of the result into out.
+/// msg is wiped by clearing it, which keeps its capacity: passing the same vector for every call
+/// saves allocating a new one.
+pub fn hash_xor_key_into(msg: &mut Vec<u8>, key: &Key, out: &mut [u8]) {
+    assert!(!msg.is_empty(), "msg vector is of empty!");
+    assert!(!key.is_empty(), "key vector is of empty!");
+    assert!(out.len() <= DIGEST_LEN, "out is longer then the digest");
+    let mut key_len = key.len();
+    while msg.len() > key_len {
+        key_len *= 2;
     }
-}
-
-///pads key by multiplying the bytes until longer then l, and then truncateing to size l
-pub fn pad_key(v: &mut Vec<u8>, l: usize) {
-    while v.len() < l {
-        v.append(&mut v.clone());
+    msg.resize(key_len, b'\x00');
+    xor_key(msg, key);
+    let hash = digest(Algorithm::SHA256, msg);
+    msg.zeroize();
+    for (i, o) in out.iter_mut().enumerate() {
+        let nibble = if i % 2 == 0 {
+            hash[i / 2] >> 4
+        } else {
+            hash[i / 2] & 0x0f
+        };
+        *o = HEX_CHARS[nibble as usize];
     }
-    v.truncate(l)
 }
 
-fn xor_key(v: &mut Vec<u8>, k: &mut Vec<u8>) -> Result<(), EncryptErr> {
-    if v.len() != k.len() {
-        return Err(EncryptErr::HashErr);
-    }
-    v.iter_mut().zip(k.iter()).for_each(|(x1, x2)| *x1 ^= *x2);
-    Ok(())
+/// xors v with the key, repeating the key as many times as needed.
+fn xor_key(v: &mut [u8], k: &Key) {
+    v.iter_mut()
+        .zip(k.as_bytes().iter().cycle())
+        .for_each(|(x1, x2)| *x1 ^= *x2);
 }
 
 #[cfg(test)]
@@ -52,9 +63,9 @@ mod tests {
     #[test]
     fn hash_diff_len() {
         let mut msg = String::from("hello world, this is a string").into_bytes();
-        let mut key = String::from("short").into_bytes();
+        let key = Key::from("short");
         assert_eq!(
-            hash_xor_key(&mut msg, &mut key).unwrap(),
+            hash_xor_key(&mut msg, &key).unwrap(),
             String::from("2b273b58b5f5cb8c45b10c1e8d92262e0e49498d5baa339f737fb87b8efd2415")
                 .into_bytes()
         );
@@ -0,0 +1,409 @@
+#![warn(missing_debug_implementations, missing_docs)]
+use crate::counter_block;
+use crate::defaults;
+use crate::error::DecryptErr;
+use crate::hasher::hash_xor_key_into;
+use crate::secret::Key;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::cmp::min;
+use std::convert::TryFrom;
+use std::io::{ErrorKind, Read, Write};
+use std::ops::Range;
+
+/// The first bytes of every encrypted file. Files written before the header was versioned start
+/// with their block size instead, as an i32, and the magic is chosen to be larger then any block
+/// size brenc ever accepted, so the two layouts can not be mistaken for each other.
+pub const MAGIC: [u8; 4] = *b"BRKN";
+
+/// The version of the header written by brenc. Legacy headers are still read.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The version reported for files written before the header was versioned.
+pub const LEGACY_VERSION: u32 = 0;
+
+/// The name of the cipher every version of the format uses: a feistel network with SHA256 round
+/// functions, in counter block mode.
+pub const CIPHER: &str = "feistel-sha256-ctr";
+
+/// The length of the key check value.
+pub const KEY_CHECK_LEN: usize = 32;
+
+/// The key check value of a file encrypted with key and nonce: a hash of both, which tells
+/// whether a key is the one the file was encrypted with, without decrypting anything. It depends
+/// on the nonce, so files encrypted with the same key do not share it.
+pub fn key_check(key: &Key, nonce: &[u8]) -> [u8; KEY_CHECK_LEN] {
+    let mut msg = b"broken key check".to_vec();
+    msg.extend_from_slice(nonce);
+    let mut out = [0u8; KEY_CHECK_LEN];
+    hash_xor_key_into(&mut msg, key, &mut out);
+    out
+}
+
+/// The header of an encrypted file, which is followed by the blocks. The current layout is:
+///
+/// [magic|version u32|block size u64|nonce size u64|rounds i32|clear text length u64|key check|nonce]
+///
+/// The legacy layout has no magic, no version, no clear text length, no key check value and i32
+/// sizes:
+///
+/// [block size i32|nonce size i32|rounds i32|nonce]
+///
+/// The clear text length is what tells the padding of the last block from the clear text, and an
+/// empty file (no blocks at all) from a truncated one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncHeader {
+    version: u32,
+    block_size: usize,
+    f_rounds: i32,
+    nonce: Vec<u8>,
+    plaintext_len: Option<u64>,
+    key_check: Option<[u8; KEY_CHECK_LEN]>,
+    block_count: u64,
+}
+
+impl EncHeader {
+    /// A header of the current version, for plaintext_len bytes of clear text encrypted in blocks
+    /// of block_size (which has to be positive) bytes. key_check is the key_check of the key and
+    /// the nonce.
+    pub fn new(
+        nonce: Vec<u8>,
+        block_size: usize,
+        f_rounds: i32,
+        plaintext_len: u64,
+        key_check: [u8; KEY_CHECK_LEN],
+    ) -> Self {
+        EncHeader {
+            version: FORMAT_VERSION,
+            block_size,
+            f_rounds,
+            nonce,
+            plaintext_len: Some(plaintext_len),
+            key_check: Some(key_check),
+            block_count: plaintext_len.div_ceil(block_size as u64),
+        }
+    }
+
+    /// Reads the header of an encrypted file of file_size bytes from the start of r, in any of
+    /// the layouts. The header is validated against itself and against file_size, so nothing read
+    /// from it can make the readers allocate, seek or index past the file.
+    /// # Errors
+    /// returns TruncatedHeader if r ends before the header does, and an error naming the field
+    /// if the header is invalid or does not match the size of the file.
+    pub fn read_from<R: Read + ?Sized>(r: &mut R, file_size: u64) -> Result<Self, DecryptErr> {
+        read_fields(r, Some(file_size)).map_err(HeaderErr::into_decrypt)
+    }
+
+    /// Same as read_from, for a stream whose length is not known (stdin, for example). The header
+    /// is only validated against itself, and its block count is the one the clear text length
+    /// tells, or 0 for older layouts, which do not store it. Readers of the blocks have to check
+    /// the length of the data themselves (see with_block_count).
+    /// # Errors
+    /// returns TruncatedHeader if r ends before the header does, and an error naming the field
+    /// if the header is invalid.
+    pub fn read_from_stream<R: Read + ?Sized>(r: &mut R) -> Result<Self, DecryptErr> {
+        read_fields(r, None).map_err(HeaderErr::into_decrypt)
+    }
+
+    /// The header with its block count set to block_count, once a reader of a stream knows it.
+    pub(crate) fn with_block_count(mut self, block_count: u64) -> Self {
+        self.block_count = block_count;
+        self
+    }
+
+    /// Writes the header to w, in the layout of its version.
+    pub fn write_to<W: Write + ?Sized>(&self, w: &mut W) -> std::io::Result<()> {
+        w.write_all(&self.to_bytes())
+    }
+
+    /// The header serialized in the layout of its version.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::with_capacity(self.data_start() as usize);
+        if self.version == LEGACY_VERSION {
+            // read_from only gives legacy headers sizes that fit in an i32
+            bytes
+                .write_i32::<LittleEndian>(self.block_size as i32)
+                .expect("could not write block size");
+            bytes
+                .write_i32::<LittleEndian>(self.nonce.len() as i32)
+                .expect("could not write nonce size");
+            bytes
+                .write_i32::<LittleEndian>(self.f_rounds)
+                .expect("could not write num rounds");
+        } else {
+            bytes.extend_from_slice(&MAGIC);
+            bytes
+                .write_u32::<LittleEndian>(self.version)
+                .expect("could not write version");
+            bytes
+                .write_u64::<LittleEndian>(self.block_size as u64)
+                .expect("could not write block size");
+            bytes
+                .write_u64::<LittleEndian>(self.nonce.len() as u64)
+                .expect("could not write nonce size");
+            bytes
+                .write_i32::<LittleEndian>(self.f_rounds)
+                .expect("could not write num rounds");
+            if let Some(len) = self.plaintext_len {
+                bytes
+                    .write_u64::<LittleEndian>(len)
+                    .expect("could not write clear text length");
+            }
+            if let Some(check) = &self.key_check {
+                bytes.extend_from_slice(check);
+            }
+        }
+        bytes.extend_from_slice(&self.nonce);
+        bytes
+    }
+
+    /// The version of the layout, or LEGACY_VERSION for unversioned headers.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// The size of every block in the file.
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// The number of fiestel rounds the file was encrypted with.
+    pub fn f_rounds(&self) -> i32 {
+        self.f_rounds
+    }
+
+    /// The nonce of the file.
+    pub fn nonce(&self) -> &[u8] {
+        &self.nonce
+    }
+
+    /// The length of the clear text, or None for files written before it was stored.
+    pub fn plaintext_len(&self) -> Option<u64> {
+        self.plaintext_len
+    }
+
+    /// The key check value, or None for files written before it was stored.
+    pub fn check_value(&self) -> Option<[u8; KEY_CHECK_LEN]> {
+        self.key_check
+    }
+
+    /// Whether key is the key the file was encrypted with, or None if the file has no key check
+    /// value to tell.
+    pub fn check_key(&self, key: &Key) -> Option<bool> {
+        self.key_check
+            .map(|check| check == key_check(key, &self.nonce))
+    }
+
+    /// The number of blocks in the file.
+    pub fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    /// The offset of the first block in the file, which is also the length of the header.
+    pub fn data_start(&self) -> u64 {
+        let fixed_len = match self.version {
+            LEGACY_VERSION => 12,
+            _ => 36 + KEY_CHECK_LEN as u64,
+        };
+        fixed_len + self.nonce.len() as u64
+    }
+
+    /// The length of all the blocks together.
+    pub fn data_len(&self) -> u64 {
+        self.block_count * self.block_size as u64
+    }
+
+    /// The offset of block number index in the file.
+    pub fn block_offset(&self, index: u64) -> u64 {
+        self.data_start() + index * self.block_size as u64
+    }
+
+    /// The first n blocks, or all of them if there are less then n.
+    pub fn head(&self, n: u64) -> Range<u64> {
+        0..min(n, self.block_count)
+    }
+
+    /// The last n blocks, or all of them if there are less then n.
+    pub fn tail(&self, n: u64) -> Range<u64> {
+        self.block_count.saturating_sub(n)..self.block_count
+    }
+}
+
+/// The errors of read_fields: io errors are kept apart, so running out of file while reading the
+/// header can be told from other io errors.
+enum HeaderErr {
+    Io(std::io::Error),
+    Invalid(DecryptErr),
+}
+
+impl HeaderErr {
+    fn into_decrypt(self) -> DecryptErr {
+        match self {
+            HeaderErr::Io(e) if e.kind() == ErrorKind::UnexpectedEof => DecryptErr::TruncatedHeader,
+            HeaderErr::Io(e) => e.into(),
+            HeaderErr::Invalid(e) => e,
+        }
+    }
+}
+
+impl From<std::io::Error> for HeaderErr {
+    fn from(e: std::io::Error) -> HeaderErr {
+        HeaderErr::Io(e)
+    }
+}
+
+impl From<DecryptErr> for HeaderErr {
+    fn from(e: DecryptErr) -> HeaderErr {
+        HeaderErr::Invalid(e)
+    }
+}
+
+fn read_fields<R: Read + ?Sized>(
+    r: &mut R,
+    file_size: Option<u64>,
+) -> Result<EncHeader, HeaderErr> {
+    let mut first = [0u8; 4];
+    r.read_exact(&mut first)?;
+    let (version, block_size, nonce_size, f_rounds, plaintext_len, key_check) = if first == MAGIC {
+        let version = r.read_u32::<LittleEndian>()?;
+        if version != FORMAT_VERSION {
+            return Err(DecryptErr::UnsupportedVersion(version).into());
+        }
+        let block_size = r.read_u64::<LittleEndian>()?;
+        let nonce_size = r.read_u64::<LittleEndian>()?;
+        let f_rounds = r.read_i32::<LittleEndian>()?;
+        let plaintext_len = r.read_u64::<LittleEndian>()?;
+        let mut key_check = [0u8; KEY_CHECK_LEN];
+        r.read_exact(&mut key_check)?;
+        (
+            version,
+            i64::try_from(block_size).unwrap_or(i64::MAX),
+            i64::try_from(nonce_size).unwrap_or(i64::MAX),
+            f_rounds,
+            Some(plaintext_len),
+            Some(key_check),
+        )
+    } else {
+        let block_size = i32::from_le_bytes(first);
+        let nonce_size = r.read_i32::<LittleEndian>()?;
+        let f_rounds = r.read_i32::<LittleEndian>()?;
+        (
+            LEGACY_VERSION,
+            block_size as i64,
+            nonce_size as i64,
+            f_rounds,
+            None,
+            None,
+        )
+    };
+
+    if block_size < defaults::MIN_BLOCK_SIZE as i64 || block_size > defaults::MAX_BLOCK_SIZE as i64
+    {
+        return Err(DecryptErr::InvalidBlockSize(block_size).into());
+    }
+    let block_size = block_size as usize;
+    // the nonce and the block counter have to fit in the feistel network of the keystream
+    if nonce_size < 0 || nonce_size as usize > counter_block::max_nonce_len(version, block_size) {
+        return Err(DecryptErr::InvalidNonceSize(nonce_size).into());
+    }
+    // the same range brenc encrypts with, so a damaged header can not decrypt with less rounds
+    if !(defaults::MIN_ROUNDS..=defaults::MAX_ROUNDS).contains(&f_rounds) {
+        return Err(DecryptErr::InvalidRounds(f_rounds).into());
+    }
+
+    let mut header = EncHeader {
+        version,
+        block_size,
+        f_rounds,
+        nonce: Vec::new(),
+        plaintext_len,
+        key_check,
+        block_count: 0,
+    };
+    let data_start = header.data_start() + nonce_size as u64;
+    if file_size.is_some_and(|size| data_start > size) {
+        return Err(DecryptErr::TruncatedHeader.into());
+    }
+    header.nonce = vec![0u8; nonce_size as usize];
+    r.read_exact(&mut header.nonce)?;
+    let file_size = match file_size {
+        Some(size) => size,
+        None => {
+            header.block_count = plaintext_len.map_or(0, |len| len.div_ceil(block_size as u64));
+            return Ok(header);
+        }
+    };
+    let data_len = file_size - data_start;
+    if data_len % block_size as u64 != 0 {
+        return Err(DecryptErr::PartialBlock.into());
+    }
+    header.block_count = data_len / block_size as u64;
+    if let Some(len) = plaintext_len {
+        if len.div_ceil(block_size as u64) != header.block_count {
+            return Err(DecryptErr::LengthMismatch(len).into());
+        }
+    }
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{key, testdata};
+    use std::fs;
+
+    #[test]
+    fn round_trips_every_version() {
+        for &(name, version) in [
+            ("golden_30.enc", FORMAT_VERSION),
+            ("legacy_30.enc", LEGACY_VERSION),
+        ]
+        .iter()
+        {
+            let file = fs::read(testdata(name)).unwrap();
+            let header = EncHeader::read_from(&mut &file[..], file.len() as u64).unwrap();
+            assert_eq!(header.version(), version);
+            assert_eq!(header.block_size(), 30);
+            assert_eq!(header.f_rounds(), 5);
+            assert_eq!(header.block_count(), 15);
+            let bytes = header.to_bytes();
+            assert_eq!(bytes.len() as u64, header.data_start());
+            assert_eq!(bytes[..], file[..bytes.len()]);
+            assert_eq!(header.data_start() + header.data_len(), file.len() as u64);
+        }
+    }
+
+    #[test]
+    fn checks_key() {
+        let key = key();
+        let file = fs::read(testdata("golden_30.enc")).unwrap();
+        let header = EncHeader::read_from(&mut &file[..], file.len() as u64).unwrap();
+        assert_eq!(header.check_key(&key), Some(true));
+        assert_eq!(
+            header.check_key(&Key::from("super_secret123!@")),
+            Some(false)
+        );
+        let file = fs::read(testdata("legacy_30.enc")).unwrap();
+        let header = EncHeader::read_from(&mut &file[..], file.len() as u64).unwrap();
+        assert_eq!(header.check_key(&key), None);
+    }
+
+    #[test]
+    fn offsets_and_ranges() {
+        let header = EncHeader::new(vec![7u8; 10], 100, 5, 1001, [1u8; KEY_CHECK_LEN]);
+        assert_eq!(header.block_count(), 11);
+        assert_eq!(header.data_start(), 78);
+        assert_eq!(header.block_offset(3), 378);
+        assert_eq!(header.head(3), 0..3);
+        assert_eq!(header.head(30), 0..11);
+        assert_eq!(header.tail(3), 8..11);
+        assert_eq!(header.tail(30), 0..11);
+
+        let mut file = Vec::new();
+        header.write_to(&mut file).unwrap();
+        file.resize((header.data_start() + header.data_len()) as usize, 0);
+        assert_eq!(
+            EncHeader::read_from(&mut &file[..], file.len() as u64).unwrap(),
+            header
+        );
+    }
+}
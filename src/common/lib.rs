@@ -1,8 +1,37 @@
+/// Bounded-memory encryption of many files at once.
+pub mod batch;
+/// Cancelling long running operations, from another thread or with ctrl-c.
+pub mod cancel;
+/// Counter block mode of operation over the feistel network, and the Blocks struct.
 pub mod counter_block;
+/// Default values and limits of the encryption parameters, shared by brenc and brgrep.
+pub mod defaults;
 pub mod error;
+/// Execution strategies and thread pools for the parallel work.
+pub mod exec;
 pub mod feistel;
 pub mod file_mng;
 pub mod hasher;
+/// The header of encrypted files: the parameters needed to decrypt them, and where their blocks
+/// are.
+pub mod header;
+/// Sources of the nonces used for encryption.
+pub mod nonce;
+/// Reading the key from a prompt, the environment, a file or a file descriptor.
+pub mod password;
+/// Progress reporting of encryption and decryption, and a progress bar for the terminal.
+pub mod progress;
+/// Searching encrypted files without writing their clear text to disk.
+pub mod search;
+/// Handling of secret key material.
+pub mod secret;
+/// Fixtures shared by the tests: the testdata files, their key, and temporary paths.
+#[cfg(test)]
+mod test_util;
+/// Checking encrypted files against their key and their original.
+pub mod verify;
+/// Walking directory trees for recursive encryption.
+pub mod walk;
 
 #[cfg(test)]
 mod tests {
@@ -11,7 +40,8 @@ mod tests {
     #[test]
     fn glob() {
         let options = MatchOptions::new();
-        let path = "/home/tomerh/Desktop/*";
-        file_mng::list_glob(path, options);
+        let path = test_util::testdata("golden_*");
+        let files = file_mng::list_glob(&path, options).unwrap();
+        assert_eq!(files.len(), 2);
     }
 }
@@ -0,0 +1,67 @@
+#![warn(missing_debug_implementations, missing_docs)]
+use rand::rngs::OsRng;
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use std::sync::Mutex;
+
+/// A source of nonces for the encryption functions. The nonce makes two encryptions of the same
+/// data with the same key produce different results, so outside of tests it must be
+/// unpredictable: use OsNonce.
+/// Sources are shared between the threads encrypting a glob, and so must be Sync.
+pub trait NonceSource: Sync {
+    /// Generates a nonce of nonce_len bytes.
+    fn nonce(&self, nonce_len: usize) -> Vec<u8>;
+}
+
+/// Generates nonces from the operating system's CSPRNG. This is the source the binaries use.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OsNonce;
+
+impl NonceSource for OsNonce {
+    fn nonce(&self, nonce_len: usize) -> Vec<u8> {
+        let mut v = vec![0u8; nonce_len];
+        OsRng.fill_bytes(&mut v);
+        v
+    }
+}
+
+/// Generates a reproducible sequence of nonces from a seed, for pinning the encrypted output in
+/// tests. Never use it for real data: anyone who knows the seed knows the nonces.
+#[derive(Debug)]
+pub struct SeededNonce {
+    rng: Mutex<ChaCha20Rng>,
+}
+
+impl SeededNonce {
+    /// Creates a source whose nonces are determined by seed.
+    pub fn new(seed: u64) -> Self {
+        SeededNonce {
+            rng: Mutex::new(ChaCha20Rng::seed_from_u64(seed)),
+        }
+    }
+}
+
+impl NonceSource for SeededNonce {
+    fn nonce(&self, nonce_len: usize) -> Vec<u8> {
+        let mut v = vec![0u8; nonce_len];
+        self.rng.lock().unwrap().fill_bytes(&mut v);
+        v
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn seeded_is_reproducible() {
+        let a = SeededNonce::new(7);
+        let b = SeededNonce::new(7);
+        assert_eq!(a.nonce(120), b.nonce(120));
+        assert_ne!(a.nonce(120), SeededNonce::new(8).nonce(120));
+    }
+
+    #[test]
+    fn os_nonces_differ() {
+        assert_ne!(OsNonce.nonce(120), OsNonce.nonce(120));
+    }
+}
@@ -0,0 +1,322 @@
+#![warn(missing_debug_implementations, missing_docs)]
+use crate::error::{ArgErr, KeyErr};
+use crate::secret::Key;
+use std::env;
+use std::fs;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// The longest line read_line reads. Its buffer is allocated once, so it never moves the
+/// password around in memory (leaving copies behind) while it grows.
+const MAX_LINE_LEN: usize = 4096;
+
+/// The signal that interrupted the prompt, or 0. Set by the handler of prompt_once.
+static PROMPT_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+/// The signals that would kill the process while echo is off, leaving the terminal without it.
+#[cfg(unix)]
+const PROMPT_SIGNALS: [libc::c_int; 3] = [libc::SIGINT, libc::SIGQUIT, libc::SIGTERM];
+
+/// Where the key comes from. Only Given puts the key on the command line, where it ends up in
+/// shell history and in the output of ps.
+#[derive(Debug, Default)]
+pub enum KeySource {
+    /// The key itself, given with -k.
+    Given(Key),
+    /// Asked for on the terminal, without echoing it.
+    #[default]
+    Prompt,
+    /// The value of this environment variable.
+    Env(String),
+    /// The content of this file, without a trailing newline.
+    File(String),
+    /// The first line read from this (already open) file descriptor.
+    Fd(i32),
+}
+
+impl KeySource {
+    /// Parses the key given with flag (-k or --key) or the place to read it from (--key-env,
+    /// --key-file, --key-fd), for the argument parsers. An empty key is refused when it is read,
+    /// wherever it comes from.
+    /// # Errors
+    /// returns ArgErr::InvalidValue for a file descriptor that is not a non-negative number.
+    pub fn parse(flag: &str, v: String) -> Result<KeySource, ArgErr> {
+        match flag {
+            "-k" | "--key" => Ok(KeySource::Given(Key::from(v))),
+            "--key-env" => Ok(KeySource::Env(v)),
+            "--key-file" => Ok(KeySource::File(v)),
+            _ => match v.parse::<i32>() {
+                Ok(fd) if fd >= 0 => Ok(KeySource::Fd(fd)),
+                _ => Err(ArgErr::InvalidValue(format!(
+                    "file descriptor should not be negative, got {}",
+                    v
+                ))),
+            },
+        }
+    }
+
+    /// Reads the key from the source. With confirm, a prompted password has to be typed twice,
+    /// so a typo does not encrypt files with a key nobody knows.
+    /// # Errors
+    /// returns KeyErr::Empty for an empty key (from any source), KeyErr::Mismatch if the
+    /// confirmation differs, KeyErr::NotSet for a missing environment variable, KeyErr::BadFd for
+    /// a file descriptor that is not open, and an IoError if
+    /// the terminal, the file or the file descriptor can not be read.
+    pub fn read(self, confirm: bool) -> Result<Key, KeyErr> {
+        let key = match self {
+            KeySource::Given(key) => key,
+            KeySource::Prompt => prompt(confirm)?,
+            KeySource::Env(var) => match env::var_os(&var) {
+                Some(v) => Key::from(os_bytes(v)),
+                None => return Err(KeyErr::NotSet(var)),
+            },
+            KeySource::File(path) => {
+                let mut bytes = fs::read(path)?;
+                trim_newline(&mut bytes);
+                Key::new(bytes)
+            }
+            KeySource::Fd(fd) => read_fd(fd)?,
+        };
+        if key.is_empty() {
+            return Err(KeyErr::Empty);
+        }
+        Ok(key)
+    }
+}
+
+/// Asks for the password on the terminal, and with confirm asks for it again.
+fn prompt(confirm: bool) -> Result<Key, KeyErr> {
+    let key = prompt_once("Password: ")?;
+    if confirm && !key.is_empty() {
+        let again = prompt_once("Confirm password: ")?;
+        if again.as_bytes() != key.as_bytes() {
+            return Err(KeyErr::Mismatch);
+        }
+    }
+    Ok(key)
+}
+
+/// Reads a line from the terminal (not from stdin, which may be the data being encrypted) with
+/// echo turned off. Echo is turned back on even if reading fails, or ctrl-c is pressed: the signal
+/// only interrupts the read, and is raised again once echo is back on.
+#[cfg(unix)]
+fn prompt_once(msg: &str) -> Result<Key, KeyErr> {
+    use std::os::unix::io::AsRawFd;
+    let mut tty = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .map_err(|e| KeyErr::IoError(format!("can not prompt for a password: {}", e)))?;
+    tty.write_all(msg.as_bytes())?;
+    tty.flush()?;
+    let fd = tty.as_raw_fd();
+    let mut term: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut term) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    PROMPT_SIGNAL.store(0, Ordering::Relaxed);
+    let mut old_actions = Vec::new();
+    for &sig in PROMPT_SIGNALS.iter() {
+        match crate::cancel::catch(sig, handle_prompt_signal, false) {
+            Ok(old) => old_actions.push((sig, old)),
+            Err(e) => {
+                restore_actions(&old_actions);
+                return Err(e.into());
+            }
+        }
+    }
+    let mut silent = term;
+    silent.c_lflag &= !libc::ECHO;
+    silent.c_lflag |= libc::ECHONL;
+    let line = if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &silent) } != 0 {
+        Err(std::io::Error::last_os_error().into())
+    } else {
+        read_line(&mut tty)
+    };
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &term) };
+    restore_actions(&old_actions);
+    let sig = PROMPT_SIGNAL.load(Ordering::Relaxed);
+    if sig != 0 {
+        // the line typed so far is wiped by the key on drop
+        drop(line);
+        let _ = tty.write_all(b"\n");
+        unsafe { libc::raise(sig) };
+        return Err(KeyErr::IoError(String::from("interrupted")));
+    }
+    line
+}
+
+#[cfg(unix)]
+extern "C" fn handle_prompt_signal(sig: libc::c_int) {
+    PROMPT_SIGNAL.store(sig, Ordering::Relaxed);
+}
+
+#[cfg(unix)]
+fn restore_actions(old_actions: &[(libc::c_int, libc::sigaction)]) {
+    for (sig, old) in old_actions.iter() {
+        crate::cancel::restore(*sig, old);
+    }
+}
+
+/// Without a way to turn echo off, the password is read from stdin as typed.
+#[cfg(not(unix))]
+fn prompt_once(msg: &str) -> Result<Key, KeyErr> {
+    let mut err = std::io::stderr();
+    err.write_all(msg.as_bytes())?;
+    err.flush()?;
+    read_line(&mut std::io::stdin().lock())
+}
+
+/// Reads the first line of the open file descriptor fd, a byte at a time, so nothing after it is
+/// consumed and fd can be stdin even when stdin is also the data. fd is left open.
+#[cfg(unix)]
+fn read_fd(fd: i32) -> Result<Key, KeyErr> {
+    use std::mem::ManuallyDrop;
+    use std::os::unix::io::FromRawFd;
+    // a File may only own a descriptor that is open
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        return Err(KeyErr::BadFd(fd));
+    }
+    let mut f = ManuallyDrop::new(unsafe { fs::File::from_raw_fd(fd) });
+    read_line(&mut *f)
+}
+
+#[cfg(not(unix))]
+fn read_fd(_fd: i32) -> Result<Key, KeyErr> {
+    Err(KeyErr::IoError(String::from(
+        "reading the key from a file descriptor is only supported on unix",
+    )))
+}
+
+/// Reads r up to the end of the line (which is dropped) or of r, a byte at a time.
+fn read_line(r: &mut dyn Read) -> Result<Key, KeyErr> {
+    let mut line: Vec<u8> = Vec::with_capacity(MAX_LINE_LEN);
+    let mut byte = [0u8];
+    loop {
+        match r.read(&mut byte) {
+            Ok(0) => break,
+            Ok(_) if byte[0] == b'\n' => break,
+            Ok(_) if line.len() == MAX_LINE_LEN => {
+                // wiped by the key on drop
+                drop(Key::new(line));
+                return Err(KeyErr::IoError(format!(
+                    "the key is longer then {} bytes",
+                    MAX_LINE_LEN
+                )));
+            }
+            Ok(_) => line.push(byte[0]),
+            // unless the prompt was interrupted on purpose
+            Err(e)
+                if e.kind() == std::io::ErrorKind::Interrupted
+                    && PROMPT_SIGNAL.load(Ordering::Relaxed) == 0 =>
+            {
+                continue
+            }
+            Err(e) => {
+                drop(Key::new(line));
+                return Err(e.into());
+            }
+        }
+    }
+    trim_newline(&mut line);
+    Ok(Key::new(line))
+}
+
+/// Drops a trailing "\n" or "\r\n".
+fn trim_newline(bytes: &mut Vec<u8>) {
+    if bytes.last() == Some(&b'\n') {
+        bytes.pop();
+    }
+    if bytes.last() == Some(&b'\r') {
+        bytes.pop();
+    }
+}
+
+#[cfg(unix)]
+fn os_bytes(s: std::ffi::OsString) -> Vec<u8> {
+    use std::os::unix::ffi::OsStringExt;
+    s.into_vec()
+}
+
+#[cfg(not(unix))]
+fn os_bytes(s: std::ffi::OsString) -> Vec<u8> {
+    s.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+
+    #[test]
+    fn key_sources() {
+        let key = KeySource::Given(Key::from("pw")).read(true).unwrap();
+        assert_eq!(key.as_bytes(), b"pw");
+        assert!(matches!(
+            KeySource::Given(Key::from("")).read(false),
+            Err(KeyErr::Empty)
+        ));
+
+        let var = format!("BROKEN_TEST_KEY_{}", std::process::id());
+        env::set_var(&var, "from env");
+        let key = KeySource::Env(var.clone()).read(false).unwrap();
+        env::remove_var(&var);
+        assert_eq!(key.as_bytes(), b"from env");
+        assert!(matches!(
+            KeySource::Env(var).read(false),
+            Err(KeyErr::NotSet(_))
+        ));
+
+        let path = temp_path("key_file");
+        fs::write(&path, b"from file\r\n").unwrap();
+        let key = KeySource::File(path.clone()).read(false);
+        fs::write(&path, b"\n").unwrap();
+        let empty = KeySource::File(path.clone()).read(false);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(key.unwrap().as_bytes(), b"from file");
+        assert!(matches!(empty, Err(KeyErr::Empty)));
+    }
+
+    #[test]
+    fn parse_sources() {
+        assert!(matches!(
+            KeySource::parse("-k", String::from("pw")),
+            Ok(KeySource::Given(_))
+        ));
+        assert!(matches!(
+            KeySource::parse("--key-env", String::from("V")),
+            Ok(KeySource::Env(ref v)) if v == "V"
+        ));
+        assert!(matches!(
+            KeySource::parse("--key-fd", String::from("3")),
+            Ok(KeySource::Fd(3))
+        ));
+        for bad in &["-1", "x"] {
+            assert!(matches!(
+                KeySource::parse("--key-fd", String::from(*bad)),
+                Err(ArgErr::InvalidValue(_))
+            ));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn key_from_fd() {
+        use std::os::unix::io::AsRawFd;
+        let path = temp_path("key_fd");
+        fs::write(&path, b"first line\nsecond line\n").unwrap();
+        let mut f = fs::File::open(&path).unwrap();
+        let key = KeySource::Fd(f.as_raw_fd()).read(false).unwrap();
+        // only the first line is read, and the descriptor is still open
+        let mut rest = String::new();
+        f.read_to_string(&mut rest).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(key.as_bytes(), b"first line");
+        assert_eq!(rest, "second line\n");
+        // a descriptor that is not open is refused before it is read
+        assert!(matches!(
+            KeySource::Fd(i32::MAX).read(false),
+            Err(KeyErr::BadFd(i32::MAX))
+        ));
+    }
+}
@@ -0,0 +1,195 @@
+#![warn(missing_debug_implementations, missing_docs)]
+use std::fmt;
+use std::io::{stderr, IsTerminal, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Called as the blocks of a file are encrypted or decrypted, with the number of bytes (of whole
+/// blocks, so with the padding of the last one) and of blocks done since the last call. It is
+/// called from the worker threads, once for every chunk of blocks rather then for every block.
+pub type Progress<'a> = dyn Fn(u64, u64) + Sync + 'a;
+
+/// Same as Progress, for the functions that handle many files: called with the path of the file
+/// the bytes and blocks are of.
+pub type FileProgress<'a> = dyn Fn(&str, u64, u64) + Sync + 'a;
+
+/// A Progress that ignores the updates.
+pub fn ignore(_bytes: u64, _blocks: u64) {}
+
+/// A FileProgress that ignores the updates.
+pub fn ignore_files(_path: &str, _bytes: u64, _blocks: u64) {}
+
+/// The shortest time between two draws of a Bar, so fast updates do not flood the terminal.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The number of characters in the bar itself.
+const BAR_WIDTH: usize = 30;
+
+/// A progress bar drawn on stderr, on a single line that is redrawn as bytes are done. It is only
+/// drawn if stderr is a terminal, so it never ends up in logs or pipes. The bar can be updated from
+/// many threads at once.
+pub struct Bar {
+    total: u64,
+    done: AtomicU64,
+    visible: bool,
+    /// when the bar was last drawn, or None if it was not drawn yet.
+    last_draw: Mutex<Option<Instant>>,
+}
+
+impl fmt::Debug for Bar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Bar")
+            .field("total", &self.total)
+            .field("done", &self.done.load(Ordering::Relaxed))
+            .field("visible", &self.visible)
+            .finish()
+    }
+}
+
+impl Bar {
+    /// A bar for total bytes, drawn if stderr is a terminal.
+    pub fn new(total: u64) -> Self {
+        Bar {
+            total,
+            done: AtomicU64::new(0),
+            visible: stderr().is_terminal(),
+            last_draw: Mutex::new(None),
+        }
+    }
+
+    /// A bar that is never drawn, for when something else is written to the terminal (the clear
+    /// text of brenc cat, for example).
+    pub fn hidden() -> Self {
+        Bar {
+            visible: false,
+            ..Bar::new(0)
+        }
+    }
+
+    /// A bar for the total size of the files at paths (files that can not be read count as
+    /// empty), drawn if stderr is a terminal.
+    pub fn for_files(paths: &[String]) -> Self {
+        let total = paths
+            .iter()
+            .map(|p| std::fs::metadata(p).map(|m| m.len()).unwrap_or(0))
+            .sum();
+        Bar::new(total)
+    }
+
+    /// for_files, for commands that print their results to stdout while the files are read
+    /// (brenc cat, brgrep): the bar is hidden when stdout is a terminal, so it does not garble
+    /// them.
+    pub fn unless_stdout_is_terminal(paths: &[String]) -> Self {
+        if std::io::stdout().is_terminal() {
+            Bar::hidden()
+        } else {
+            Bar::for_files(paths)
+        }
+    }
+
+    /// Adds bytes done in the file at path, and redraws the bar if it was not drawn in the last
+    /// REDRAW_INTERVAL. Fits FileProgress: `&|p, bytes, _| bar.advance(p, bytes)`.
+    pub fn advance(&self, path: &str, bytes: u64) {
+        let done = self.done.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        if !self.visible {
+            return;
+        }
+        // a thread that finds another one drawing skips this draw
+        let mut last_draw = match self.last_draw.try_lock() {
+            Ok(l) => l,
+            Err(_) => return,
+        };
+        let now = Instant::now();
+        if matches!(*last_draw, Some(t) if now.duration_since(t) < REDRAW_INTERVAL) {
+            return;
+        }
+        *last_draw = Some(now);
+        let name = Path::new(path)
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut err = stderr().lock();
+        let _ = write!(err, "\r{}\x1b[K", format_bar(done, self.total, &name));
+        let _ = err.flush();
+    }
+
+    /// Clears the line of the bar, if it was drawn, so whatever is printed next starts at the
+    /// begining of an empty line.
+    pub fn finish(&self) {
+        let drawn = match self.last_draw.lock() {
+            Ok(l) => l.is_some(),
+            Err(_) => true,
+        };
+        if self.visible && drawn {
+            let mut err = stderr().lock();
+            let _ = write!(err, "\r\x1b[K");
+            let _ = err.flush();
+        }
+    }
+}
+
+/// The line of a bar with done of total bytes done, the last of them in the file name.
+fn format_bar(done: u64, total: u64, name: &str) -> String {
+    // the padding of the last blocks can take done past the size of the files
+    let done = done.min(total);
+    let filled = if total == 0 {
+        BAR_WIDTH
+    } else {
+        (done as u128 * BAR_WIDTH as u128 / total as u128) as usize
+    };
+    let percent = if total == 0 {
+        100
+    } else {
+        done as u128 * 100 / total as u128
+    };
+    format!(
+        "[{}{}] {:>3}% {} / {} {}",
+        "#".repeat(filled),
+        " ".repeat(BAR_WIDTH - filled),
+        percent,
+        format_size(done),
+        format_size(total),
+        name
+    )
+}
+
+/// bytes in the largest unit it is at least one of, with a single decimal.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bar_line() {
+        assert_eq!(
+            format_bar(512, 2048, "a.txt"),
+            "[#######                       ]  25% 512 B / 2.0 KiB a.txt"
+        );
+        // padding past the end, and nothing to do
+        assert!(format_bar(3000, 2048, "a").starts_with(&format!("[{}] 100%", "#".repeat(30))));
+        assert!(format_bar(0, 0, "a").contains("100% 0 B / 0 B"));
+        assert_eq!(format_size(5 << 30), "5.0 GiB");
+
+        let bar = Bar::hidden();
+        bar.advance("a", 10);
+        bar.advance("b", 5);
+        assert_eq!(bar.done.load(Ordering::Relaxed), 15);
+        bar.finish();
+    }
+}
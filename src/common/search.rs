@@ -0,0 +1,55 @@
+#![warn(missing_debug_implementations, missing_docs)]
+use crate::exec::Execution;
+use crate::file_mng::MappedEncFile;
+use crate::progress::FileProgress;
+use crate::secret::Key;
+use grep::printer::Standard;
+use grep::regex::RegexMatcher;
+use grep::searcher::Searcher;
+use std::error::Error;
+use termcolor::{ColorChoice, StandardStream};
+
+/// Searches the encrypted files at paths for the regular expression exp, and prints the matching
+/// lines to stdout, like grep. Every file is decrypted in memory (in the pool of execution), so
+/// its clear text is never written to disk. With head_tail, only the first (Some(true)) or last
+/// (Some(false)) count blocks of every file are searched. progress is called with the path of
+/// every file as its blocks are decrypted.
+/// # Errors
+/// returns an error if exp is not a valid regular expression, or a file can not be read or
+/// decrypted.
+pub fn grep_files(
+    paths: &[String],
+    key: &Key,
+    exp: &str,
+    head_tail: Option<bool>,
+    count: u64,
+    execution: &Execution,
+    progress: &FileProgress,
+) -> Result<(), Box<dyn Error>> {
+    let matcher = RegexMatcher::new(exp)?;
+    let mut printer = Standard::new(StandardStream::stdout(ColorChoice::Always));
+    for p in paths.iter() {
+        let f = MappedEncFile::open(p)?;
+        let header = f.header();
+        let range = match head_tail {
+            Some(true) => header.head(count),
+            Some(false) => header.tail(count),
+            None => 0..header.block_count(),
+        };
+        // the file is mapped, so its blocks are numbered in a usize
+        let range = range.start as usize..range.end as usize;
+        let file_progress = |bytes, blocks| progress(p, bytes, blocks);
+        let dec_bytes = execution.install(|| f.decrypt(key, range, &file_progress))?;
+        // only print the file name when grepping more then one file, like grep does
+        if paths.len() > 1 {
+            Searcher::new().search_slice(
+                &matcher,
+                &dec_bytes,
+                printer.sink_with_path(&matcher, p),
+            )?;
+        } else {
+            Searcher::new().search_slice(&matcher, &dec_bytes, printer.sink(&matcher))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,119 @@
+#![warn(missing_debug_implementations, missing_docs)]
+use std::fmt;
+use zeroize::Zeroize;
+
+/// Key holds secret key material (the user password, or a round key derived from it).
+/// The bytes are locked in memory where the os permits it, so they are not swapped to disk, and
+/// are wiped when the key is dropped. Key is deliberately not Clone: pass it by reference instead
+/// of copying it around.
+#[derive(Default)]
+pub struct Key {
+    bytes: Vec<u8>,
+}
+
+impl Key {
+    /// Takes ownership of the bytes and locks their whole buffer in memory, spare capacity
+    /// included.
+    pub fn new(bytes: Vec<u8>) -> Self {
+        lock(&bytes);
+        Key { bytes }
+    }
+
+    /// Generates a new key of length len, by repeating the bytes of this key and truncating the
+    /// result. The buffer is allocated once, so no unwiped copy of the key is left behind.
+    pub fn padded(&self, len: usize) -> Self {
+        assert!(!self.is_empty(), "key should not be empty");
+        let mut bytes = Vec::with_capacity(len);
+        bytes.extend(self.bytes.iter().cycle().take(len));
+        Key::new(bytes)
+    }
+
+    /// The raw bytes of the key.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The raw bytes of the key, for mutating the key in place.
+    pub fn as_mut_bytes(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+
+    /// The length of the key in bytes.
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Returns true if the key has no bytes.
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+impl From<Vec<u8>> for Key {
+    fn from(bytes: Vec<u8>) -> Key {
+        Key::new(bytes)
+    }
+}
+
+impl From<String> for Key {
+    fn from(s: String) -> Key {
+        Key::new(s.into_bytes())
+    }
+}
+
+impl From<&str> for Key {
+    fn from(s: &str) -> Key {
+        Key::new(s.as_bytes().to_vec())
+    }
+}
+
+impl Drop for Key {
+    fn drop(&mut self) {
+        // wipes the spare capacity as well, before the buffer can be swapped out. The pages are
+        // deliberately left locked: munlock works on whole pages and is not counted, so unlocking
+        // them would unlock any other live key sharing a page with this one (the padded keys of
+        // every feistel network share pages with the password). The locks are released when the
+        // process exits.
+        self.bytes.zeroize();
+    }
+}
+
+/// Never prints the key itself, so keys can safely end up in debug output and error messages.
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Key([REDACTED; {}])", self.len())
+    }
+}
+
+/// Locks the pages holding the buffer of bytes in memory. The whole capacity is locked, since the
+/// spare capacity may hold key material too (a String the key was taken from, for example).
+/// This is best effort: mlock fails for unprivileged users past RLIMIT_MEMLOCK, in which case the
+/// key is still wiped on drop. The pages stay locked until the process exits, see Drop for Key.
+#[cfg(unix)]
+fn lock(bytes: &Vec<u8>) {
+    if bytes.capacity() != 0 {
+        unsafe {
+            libc::mlock(bytes.as_ptr() as *const libc::c_void, bytes.capacity());
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn lock(_bytes: &Vec<u8>) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn padded_key() {
+        let key = Key::from("abc");
+        assert_eq!(key.padded(7).as_bytes(), b"abcabca");
+        assert_eq!(key.padded(2).as_bytes(), b"ab");
+    }
+
+    #[test]
+    fn debug_is_redacted() {
+        let key = Key::from("super_secret");
+        assert_eq!(format!("{:?}", key), "Key([REDACTED; 12])");
+    }
+}
@@ -0,0 +1,25 @@
+use crate::secret::Key;
+use std::env;
+
+// The golden files pin the full on-disk format. After an intended format change, regenerate
+// them with:
+//   BROKEN_BLESS=1 cargo test golden
+
+/// The key every file in testdata was encrypted with.
+pub fn key() -> Key {
+    Key::from("super_secret123!@#")
+}
+
+/// The path of the file name in testdata.
+pub fn testdata(name: &str) -> String {
+    format!("{}/testdata/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+/// A path in the temporary directory for a file (or directory) of a test, unique to name and to
+/// the process running the tests.
+pub fn temp_path(name: &str) -> String {
+    env::temp_dir()
+        .join(format!("broken_{}_{}", std::process::id(), name))
+        .display()
+        .to_string()
+}
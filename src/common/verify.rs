@@ -0,0 +1,206 @@
+#![warn(missing_debug_implementations, missing_docs)]
+use crate::counter_block;
+use crate::error::DecryptErr;
+use crate::file_mng;
+use crate::header::EncHeader;
+use crate::progress;
+use crate::secret::Key;
+use crypto_hash::{Algorithm, Hasher};
+use std::cmp::min;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Seek, SeekFrom, Write};
+
+/// What verify_file found out about an encrypted file whose header is valid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Report {
+    /// key: whether the key is the one the file was encrypted with, or None if the file has no
+    /// key check value to tell.
+    pub key: Option<bool>,
+    /// plaintext: whether the decrypted file hashes the same as the original, or None if it was
+    /// not compared.
+    pub plaintext: Option<bool>,
+}
+
+impl Report {
+    /// Whether none of the checks failed.
+    pub fn passed(&self) -> bool {
+        self.key != Some(false) && self.plaintext != Some(false)
+    }
+}
+
+/// Verifies the encrypted file at path: its header has to be valid and match the length of the
+/// file (so truncated or appended files fail), and key has to match its key check value. If
+/// original is given, the SHA256 of the decrypted file also has to match the SHA256 of original.
+/// The format has no MAC, so blocks modified in place can only be found by comparing with the
+/// original.
+/// # Errors
+/// returns an error if a file can not be read or the header is invalid. Checks that fail are
+/// reported in the Report.
+pub fn verify_file(path: &str, key: &Key, original: Option<&str>) -> Result<Report, DecryptErr> {
+    let mut orig = match original {
+        Some(o) => Some(File::open(o)?),
+        None => None,
+    };
+    verify_with(path, key, orig.as_mut())
+}
+
+/// verify_file, with the original read from the start of the open file orig.
+fn verify_with(path: &str, key: &Key, orig: Option<&mut File>) -> Result<Report, DecryptErr> {
+    let mut f = File::open(path)?;
+    let file_size = f.metadata()?.len();
+    let header = EncHeader::read_from(&mut f, file_size)?;
+    let key_ok = header.check_key(key);
+    let plaintext = match orig {
+        // the clear text of a wrong key can not match, so there is no need to decrypt it
+        Some(_) if key_ok == Some(false) => Some(false),
+        Some(o) => Some(matches_original(path, &header, key, o)?),
+        None => None,
+    };
+    Ok(Report {
+        key: key_ok,
+        plaintext,
+    })
+}
+
+/// Removes original (see file_mng::shred), but only after verify_file shows that the encrypted
+/// file at path decrypts to it with key. original is opened once, and checked and shredded
+/// through the same handle, so a file put in its place in between is not removed. returns the
+/// number of bytes removed, or None if the check failed, or original changed while it was
+/// checked, and original was kept.
+/// # Errors
+/// returns an error if a file can not be read, the header is invalid, or original can not be
+/// removed.
+pub fn shred_verified(path: &str, key: &Key, original: &str) -> Result<Option<u64>, DecryptErr> {
+    let mut orig = OpenOptions::new().read(true).write(true).open(original)?;
+    let before = orig.metadata()?;
+    let report = verify_with(path, key, Some(&mut orig))?;
+    if !report.passed() || report.plaintext != Some(true) {
+        return Ok(None);
+    }
+    // written to through another handle while it was hashed
+    let after = orig.metadata()?;
+    if after.len() != before.len() || after.modified()? != before.modified()? {
+        return Ok(None);
+    }
+    Ok(Some(file_mng::shred_open(original, orig)?))
+}
+
+/// Whether the encrypted file at path, with header, decrypts to the same SHA256 as the open file
+/// orig.
+fn matches_original(
+    path: &str,
+    header: &EncHeader,
+    key: &Key,
+    orig: &mut File,
+) -> Result<bool, DecryptErr> {
+    let orig_len = orig.metadata()?.len();
+    // files written before the clear text length was stored decrypt with the padding of their
+    // last block, so their length is only known to the block
+    let same_len = match header.plaintext_len() {
+        Some(len) => len == orig_len,
+        None => orig_len.div_ceil(header.block_size() as u64) == header.block_count(),
+    };
+    if !same_len {
+        return Ok(false);
+    }
+
+    let mut hasher = Hasher::new(Algorithm::SHA256);
+    orig.seek(SeekFrom::Start(0))?;
+    io::copy(orig, &mut hasher)?;
+    let orig_hash = hasher.finish();
+    let mut prefix = Prefix {
+        inner: Hasher::new(Algorithm::SHA256),
+        left: orig_len,
+    };
+    counter_block::decrypt_file_to(path, key, &mut prefix, &progress::ignore, None)?;
+    Ok(prefix.inner.finish() == orig_hash)
+}
+
+/// A writer that passes only the first left bytes written to it on to inner, and drops the rest.
+struct Prefix<W: Write> {
+    inner: W,
+    left: u64,
+}
+
+impl<W: Write> Write for Prefix<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = min(self.left, buf.len() as u64) as usize;
+        self.inner.write_all(&buf[..len])?;
+        self.left -= len as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{key, temp_path, testdata};
+    use std::fs;
+
+    #[test]
+    fn verify_goldens() {
+        let key = key();
+        let clear = testdata("clear.txt");
+        for name in &["golden_30.enc", "golden_301.enc", "legacy_30.enc"] {
+            let report = verify_file(&testdata(name), &key, Some(&clear)).unwrap();
+            assert!(report.passed(), "{}: {:?}", name, report);
+            assert_eq!(report.plaintext, Some(true));
+        }
+        let report = verify_file(&testdata("golden_30.enc"), &key, None).unwrap();
+        assert_eq!(report.key, Some(true));
+        assert_eq!(report.plaintext, None);
+        let report = verify_file(&testdata("legacy_30.enc"), &key, None).unwrap();
+        assert_eq!(report.key, None);
+        assert!(report.passed());
+    }
+
+    #[test]
+    fn verify_failures() {
+        let clear = testdata("clear.txt");
+        let wrong = Key::from("super_secret123!@");
+        let report = verify_file(&testdata("golden_30.enc"), &wrong, Some(&clear)).unwrap();
+        assert_eq!(report.key, Some(false));
+        assert!(!report.passed());
+        // without a key check value, only the comparison tells the key is wrong
+        let report = verify_file(&testdata("legacy_30.enc"), &wrong, Some(&clear)).unwrap();
+        assert_eq!(report.plaintext, Some(false));
+
+        let key = key();
+        let mut golden = fs::read(testdata("golden_30.enc")).unwrap();
+        // a byte of the first block, the last one ends with padding
+        golden[200] ^= 1;
+        let last = golden.len() - 1;
+        let path = temp_path("flipped.enc");
+        fs::write(&path, &golden).unwrap();
+        let report = verify_file(&path, &key, Some(&clear)).unwrap();
+        let truncated = fs::write(&path, &golden[..last]).map(|_| verify_file(&path, &key, None));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(report.key, Some(true));
+        assert_eq!(report.plaintext, Some(false));
+        assert!(matches!(truncated.unwrap(), Err(DecryptErr::PartialBlock)));
+    }
+
+    #[test]
+    fn shred_only_verified() {
+        let key = key();
+        let original = temp_path("shred_original");
+        let enc = temp_path("shred_original_enc");
+        fs::write(&original, b"some clear text, some clear text").unwrap();
+        counter_block::encrypt_file(&original, &enc, &key, 10, 5, &crate::nonce::OsNonce).unwrap();
+        let wrong_key = shred_verified(&enc, &Key::from("nope"), &original).unwrap();
+        let kept = fs::metadata(&original).is_ok();
+        let removed = shred_verified(&enc, &key, &original).unwrap();
+        let gone = fs::metadata(&original).is_err();
+        fs::remove_file(&enc).unwrap();
+
+        assert_eq!(wrong_key, None);
+        assert!(kept);
+        assert_eq!(removed, Some(32));
+        assert!(gone);
+    }
+}
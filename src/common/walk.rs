@@ -0,0 +1,171 @@
+#![warn(missing_debug_implementations, missing_docs)]
+use glob::{MatchOptions, Pattern, PatternError};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What walk does with symbolic links.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Symlinks {
+    /// Links are left out, whether they point to files or to directories.
+    #[default]
+    Skip,
+    /// Links are walked as if they were the file or directory they point to. A link to a
+    /// directory the walk is already inside of (one of the directories above the link) is left
+    /// out, so links can not make the walk loop. A directory linked from elsewhere is walked
+    /// under every path that reaches it.
+    Follow,
+}
+
+/// Which files under the root walk returns.
+#[derive(Debug, Clone, Default)]
+pub struct WalkOptions {
+    /// include: if not empty, only files whose path (relative to the root) matches one of these
+    /// are returned.
+    pub include: Vec<Pattern>,
+    /// exclude: files and directories whose relative path matches one of these are left out,
+    /// with everything under them.
+    pub exclude: Vec<Pattern>,
+    /// symlinks: what to do with symbolic links.
+    pub symlinks: Symlinks,
+}
+
+impl WalkOptions {
+    /// Parses a glob pattern for include or exclude. `*` also matches `/`, so `*.log` matches log
+    /// files in every directory.
+    pub fn pattern(p: &str) -> Result<Pattern, PatternError> {
+        Pattern::new(p)
+    }
+
+    fn matches(patterns: &[Pattern], rel: &Path) -> bool {
+        patterns
+            .iter()
+            .any(|p| p.matches_path_with(rel, MatchOptions::new()))
+    }
+}
+
+/// Walks the directory tree under root, and returns the paths of all the regular files in it that
+/// options let through, relative to root, sorted.
+/// # Errors
+/// returns an error if root or any directory under it can not be read.
+pub fn walk(root: &Path, options: &WalkOptions) -> io::Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    // the canonical paths of the directories from root down to the one being walked
+    let mut ancestors: Vec<PathBuf> = vec![root.canonicalize()?];
+    walk_dir(root, Path::new(""), options, &mut ancestors, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+fn walk_dir(
+    root: &Path,
+    rel_dir: &Path,
+    options: &WalkOptions,
+    ancestors: &mut Vec<PathBuf>,
+    files: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    let mut entries = fs::read_dir(root.join(rel_dir))?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let rel = rel_dir.join(entry.file_name());
+        if WalkOptions::matches(&options.exclude, &rel) {
+            continue;
+        }
+        let mut file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            match options.symlinks {
+                Symlinks::Skip => continue,
+                // a dangling link is left out
+                Symlinks::Follow => match fs::metadata(entry.path()) {
+                    Ok(meta) => file_type = meta.file_type(),
+                    Err(_) => continue,
+                },
+            }
+        }
+        if file_type.is_dir() {
+            let canonical = entry.path().canonicalize()?;
+            if !ancestors.contains(&canonical) {
+                ancestors.push(canonical);
+                walk_dir(root, &rel, options, ancestors, files)?;
+                ancestors.pop();
+            }
+        } else if file_type.is_file()
+            && (options.include.is_empty() || WalkOptions::matches(&options.include, &rel))
+        {
+            files.push(rel);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+
+    fn tree(name: &str) -> PathBuf {
+        let root = PathBuf::from(temp_path(&format!("walk_{}", name)));
+        for dir in &["a/b", "c", "skip"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in &["top.txt", "a/x.log", "a/b/y.txt", "c/z.txt", "skip/w.txt"] {
+            fs::write(root.join(file), file.as_bytes()).unwrap();
+        }
+        root
+    }
+
+    fn names(files: Vec<PathBuf>) -> Vec<String> {
+        files.iter().map(|p| p.display().to_string()).collect()
+    }
+
+    #[test]
+    fn include_exclude() {
+        let root = tree("patterns");
+        let all = walk(&root, &WalkOptions::default()).unwrap();
+        let options = WalkOptions {
+            include: vec![WalkOptions::pattern("*.txt").unwrap()],
+            exclude: vec![WalkOptions::pattern("skip").unwrap()],
+            ..WalkOptions::default()
+        };
+        let some = walk(&root, &options).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(
+            names(all),
+            vec!["a/b/y.txt", "a/x.log", "c/z.txt", "skip/w.txt", "top.txt"]
+        );
+        assert_eq!(names(some), vec!["a/b/y.txt", "c/z.txt", "top.txt"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks() {
+        use std::os::unix::fs::symlink;
+        let root = tree("symlinks");
+        symlink(root.join("c"), root.join("a/link_dir")).unwrap();
+        symlink(root.join("top.txt"), root.join("link_file")).unwrap();
+        // a loop back to the root
+        symlink(&root, root.join("c/loop")).unwrap();
+        let skipped = walk(&root, &WalkOptions::default()).unwrap();
+        let options = WalkOptions {
+            symlinks: Symlinks::Follow,
+            ..WalkOptions::default()
+        };
+        let followed = walk(&root, &options).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(skipped.len(), 5);
+        assert_eq!(
+            names(followed),
+            vec![
+                "a/b/y.txt",
+                "a/link_dir/z.txt",
+                "a/x.log",
+                "c/z.txt",
+                "link_file",
+                "skip/w.txt",
+                "top.txt"
+            ]
+        );
+    }
+}
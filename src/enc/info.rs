@@ -0,0 +1,114 @@
+use common::error::DecryptErr;
+use common::header::{EncHeader, CIPHER};
+use std::fs::File;
+
+/// Reads the header of the encrypted file at path. Only the header is read, so no key is needed.
+pub fn read_info(path: &str) -> Result<EncHeader, DecryptErr> {
+    let mut f = File::open(path)?;
+    let file_size = f.metadata()?.len();
+    EncHeader::read_from(&mut f, file_size)
+}
+
+/// The header fields of a file as lines of text, for a person to read.
+pub fn format_text(path: &str, info: &Result<EncHeader, DecryptErr>) -> String {
+    match info {
+        Ok(h) => format!(
+            "{}
+    format version: {}
+    cipher: {}
+    block size: {}
+    rounds: {}
+    nonce size: {}
+    block count: {}
+    plaintext length: {}
+    key check value: {}",
+            path,
+            h.version(),
+            CIPHER,
+            h.block_size(),
+            h.f_rounds(),
+            h.nonce().len(),
+            h.block_count(),
+            match h.plaintext_len() {
+                Some(len) => len.to_string(),
+                None => String::from("unknown (padded to whole blocks)"),
+            },
+            if h.check_value().is_some() {
+                "yes"
+            } else {
+                "no"
+            }
+        ),
+        Err(e) => format!("{}\n    {}", path, e),
+    }
+}
+
+/// The header fields of a file as a json object, for other programs to read. The plaintext
+/// length is null for files that do not store it, and a file that can not be read has only its
+/// path and an error.
+pub fn format_json(path: &str, info: &Result<EncHeader, DecryptErr>) -> String {
+    match info {
+        Ok(h) => format!(
+            "{{\"path\": {}, \"format_version\": {}, \"cipher\": {}, \"block_size\": {}, \
+             \"rounds\": {}, \"nonce_size\": {}, \"block_count\": {}, \"plaintext_len\": {}, \
+             \"key_check\": {}}}",
+            json_string(path),
+            h.version(),
+            json_string(CIPHER),
+            h.block_size(),
+            h.f_rounds(),
+            h.nonce().len(),
+            h.block_count(),
+            match h.plaintext_len() {
+                Some(len) => len.to_string(),
+                None => String::from("null"),
+            },
+            h.check_value().is_some()
+        ),
+        Err(e) => format!(
+            "{{\"path\": {}, \"error\": {}}}",
+            json_string(path),
+            json_string(&e.to_string())
+        ),
+    }
+}
+
+/// Quotes s as a json string.
+fn json_string(s: &str) -> String {
+    let mut res = String::with_capacity(s.len() + 2);
+    res.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_output() {
+        let path = format!("{}/testdata/legacy_30.enc", env!("CARGO_MANIFEST_DIR"));
+        let json = format_json("legacy \"30\"", &read_info(&path));
+        assert_eq!(
+            json,
+            "{\"path\": \"legacy \\\"30\\\"\", \"format_version\": 0, \
+             \"cipher\": \"feistel-sha256-ctr\", \"block_size\": 30, \"rounds\": 5, \
+             \"nonce_size\": 120, \"block_count\": 15, \"plaintext_len\": null, \
+             \"key_check\": false}"
+        );
+        let json = format_json("missing", &read_info("/no/such/file"));
+        assert!(json.starts_with("{\"path\": \"missing\", \"error\": \"decryption error: "));
+        assert_eq!(json_string("a\u{1}b"), "\"a\\u0001b\"");
+    }
+}
@@ -1,28 +1,66 @@
 #![warn(missing_debug_implementations)]
 use common::*;
+use std::collections::HashMap;
 use std::env::args;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::process::exit;
+mod info;
+mod output;
 mod parse_args;
+mod report;
+use cancel::CancelToken;
 use glob::MatchOptions;
 use parse_args::Args;
+use secret::Key;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let parsed_args_res = parse_args::parse_args(args().collect());
     let parsed_args = match parsed_args_res {
         Ok(parsed_args) => parsed_args,
-        Err(_) => exit(0),
+        Err(e) => {
+            eprintln!("brenc: {}", e);
+            eprintln!("see brenc --help for the commands and their options");
+            exit(2);
+        }
     };
 
     let mut enc_dec: bool = true;
     let mut head_tail: Option<bool> = None;
     let mut path: String = String::new();
-    let mut key: String = String::new();
-    let mut is_glob: bool = true;
-    let mut options: MatchOptions = MatchOptions::new();
+    let mut key_source = password::KeySource::default();
+    let options: MatchOptions = MatchOptions::new();
+    let mut block_size: usize = defaults::BLOCK_SIZE;
+    let mut rounds: i32 = defaults::ROUNDS;
+    let mut count: u64 = defaults::HEAD_TAIL_BLOCKS;
+    let mut execution = exec::Execution::default();
+    let mut info_path: Option<String> = None;
+    let mut json: bool = false;
+    let mut verify_path: Option<String> = None;
+    let mut compare: bool = false;
+    let mut recursive: bool = false;
+    let mut output: Option<String> = None;
+    let mut suffix = String::from(defaults::ENC_SUFFIX);
+    let mut walk_options = walk::WalkOptions::default();
+    let mut force: bool = false;
+    let mut shred: bool = false;
+    let mut keep_going: bool = false;
+    let mut cat: bool = false;
+    let mut grep_exp: Option<String> = None;
 
     for arg in parsed_args.into_iter() {
         match arg {
-            Args::Key(v) => key = v,
+            Args::Help(c) => {
+                println!("{}", parse_args::usage(c));
+                return Ok(());
+            }
+            Args::Version => {
+                println!("brenc {}", env!("CARGO_PKG_VERSION"));
+                return Ok(());
+            }
+            Args::Key(v) => key_source = v,
             Args::Encrypt(v) => {
                 path = v;
                 enc_dec = true;
@@ -31,168 +69,578 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 path = v;
                 enc_dec = false;
             }
+            Args::Cat(v) => {
+                path = v;
+                enc_dec = false;
+                cat = true;
+            }
+            Args::Grep(exp, v) => {
+                path = v;
+                enc_dec = false;
+                grep_exp = Some(exp);
+            }
             Args::Head => head_tail = Some(true),
             Args::Tail => head_tail = Some(false),
+            Args::BlockSize(v) => block_size = v,
+            Args::Rounds(v) => rounds = v,
+            Args::Count(v) => count = v,
+            Args::Threads(v) => execution = exec::Execution::with_threads(exec::Strategy::Auto, v)?,
+            Args::Info(v) => info_path = Some(v),
+            Args::Json => json = true,
+            Args::Verify(v) => verify_path = Some(v),
+            Args::Compare => compare = true,
+            Args::Recursive => recursive = true,
+            Args::Output(v) => output = Some(v),
+            Args::Suffix(v) => suffix = v,
+            Args::Include(v) => walk_options.include.push(v),
+            Args::Exclude(v) => walk_options.exclude.push(v),
+            Args::Symlinks(v) => walk_options.symlinks = v,
+            Args::Force => force = true,
+            Args::Shred => shred = true,
+            Args::KeepGoing => keep_going = true,
         }
     }
 
-    if enc_dec {
-        if is_glob {
-            encrypt_glob(&path, &key, options)?;
+    if let Some(p) = info_path {
+        if !print_info(&p, json)? {
+            exit(1);
+        }
+        return Ok(());
+    }
+    // new files get a key nobody knows if the password is mistyped, so it is typed twice
+    let key = key_source.read(enc_dec && verify_path.is_none())?;
+    if let Some(p) = verify_path {
+        if !verify(&p, &key, compare, &suffix, &execution)? {
+            exit(1);
+        }
+        return Ok(());
+    }
+    if let Some(exp) = grep_exp {
+        let paths = file_mng::list_glob(&path, options)?;
+        if paths.is_empty() {
+            eprintln!("no files match {}", path);
+            exit(1);
+        }
+        let bar = progress::Bar::unless_stdout_is_terminal(&paths);
+        let res = search::grep_files(
+            &paths,
+            &key,
+            &exp,
+            head_tail,
+            count,
+            &execution,
+            &|p, bytes, _| bar.advance(p, bytes),
+        );
+        bar.finish();
+        return res;
+    }
+    if cat {
+        // every file is decrypted to stdout, one after the other
+        let paths = if path == "-" {
+            vec![path.clone()]
         } else {
-            encrypt_single(&path, &key)?;
-        }
-    } else if is_glob {
-        match head_tail {
-            Some(t) => {
-                if t {
-                    decrypt_glob_head(&path, &key, options)?
-                } else {
-                    decrypt_glob_tail(&path, &key, options)?
-                }
+            file_mng::list_glob(&path, options)?
+        };
+        if paths.is_empty() {
+            eprintln!("no files match {}", path);
+            exit(1);
+        }
+        let bar = progress::Bar::unless_stdout_is_terminal(&paths);
+        for p in paths.iter() {
+            let progress = |bytes, _| bar.advance(p, bytes);
+            let res = execution.install(|| {
+                let mut out = io::stdout().lock();
+                decrypt_to(p, &mut out, &key, head_tail, count, &progress, None)?;
+                Ok::<(), error::DecryptErr>(out.flush()?)
+            });
+            if let Err(e) = res {
+                bar.finish();
+                return Err(e.into());
             }
-            None => decrypt_glob(&path, &key, options)?,
         }
-    } else {
-        match head_tail {
-            Some(t) => {
-                if t {
-                    decrypt_single_head(&path, &key)?
-                } else {
-                    decrypt_single_tail(&path, &key)?
+        bar.finish();
+        return Ok(());
+    }
+
+    // from here on files are written, so ctrl-c stops brenc cleanly and removes whatever was not
+    // finished, instead of killing it with the files half written
+    let cancel = cancel::on_interrupt()?;
+    if path == "-" || output.as_deref() == Some("-") {
+        let output = output.unwrap_or_default();
+        let input = if path == "-" {
+            path
+        } else {
+            single_match(&path)?
+        };
+        if output != "-" {
+            if let Some(dir) = Path::new(&output).parent() {
+                if !dir.as_os_str().is_empty() {
+                    fs::create_dir_all(dir)?;
                 }
             }
-            None => decrypt_single(&path, &key)?,
         }
+        if enc_dec {
+            let res = execution.install(|| {
+                encrypt_stdio(&input, &output, &key, block_size, rounds, force, &cancel)
+            });
+            exit_if_cancelled(&cancel);
+            res?;
+        } else {
+            let res = execution
+                .install(|| decrypt_stdio(&input, &output, &key, head_tail, count, force, &cancel));
+            exit_if_cancelled(&cancel);
+            res?;
+        }
+        return Ok(());
     }
-    Ok(())
+
+    let (paths, outputs) = if recursive {
+        output::map_tree(
+            Path::new(&path),
+            Path::new(&output.unwrap_or_default()),
+            &walk_options,
+            enc_dec,
+            &suffix,
+        )?
+    } else {
+        let paths = file_mng::list_glob(&path, options)?;
+        if paths.is_empty() {
+            eprintln!("no files match {}", path);
+            exit(1);
+        }
+        let outputs = output::map_outputs(
+            &paths,
+            &output::Output::new(output.as_deref()),
+            enc_dec,
+            &suffix,
+        )?;
+        (paths, outputs)
+    };
+    for dir in outputs.iter().filter_map(|o| o.parent()) {
+        if !dir.as_os_str().is_empty() {
+            fs::create_dir_all(dir)?;
+        }
+    }
+
+    if enc_dec {
+        let options = batch::BatchOptions {
+            execution: &execution,
+            overwrite: force,
+            keep_going,
+            cancel: Some(&cancel),
+            ..batch::BatchOptions::default()
+        };
+        let shred = if shred { Some(&execution) } else { None };
+        let res = encrypt_files(&paths, &outputs, &key, block_size, rounds, &options, shred);
+        exit_if_cancelled(&cancel);
+        return finish(&res?, keep_going, "encrypted");
+    }
+    // decryption runs in the parallel iterators of counter_block, so running it in the pool is
+    // enough to limit it to the requested threads.
+    let res = execution.install(|| {
+        decrypt_files(
+            &paths, &outputs, &key, head_tail, count, force, keep_going, &cancel,
+        )
+    });
+    exit_if_cancelled(&cancel);
+    finish(&res?, keep_going, "decrypted")
 }
 
-/// Wrapper function for encrypting a single file.
-/// takes a path to a single file and a password and preforms reading of the file, encryption and
-/// writing to a new file.
-fn encrypt_single(path: &str, key: &str) -> Result<(), error::EncryptErr> {
-    let blocks = counter_block::Blocks::from_clear_file(&path, &key, 30, 5)?;
-    let new_path = format!("{}_enc", path);
-    blocks.into_enc_file(&new_path)
+/// Exits with report::EXIT_CANCELLED if cancel was cancelled (by ctrl-c) while files were written.
+/// By then the files that were not finished are already removed.
+fn exit_if_cancelled(cancel: &CancelToken) {
+    if cancel.is_cancelled() {
+        eprintln!("brenc: cancelled, the files that were not finished were removed");
+        exit(report::EXIT_CANCELLED);
+    }
 }
 
-/// Wrapper function for encrypting a glob style path.
-/// Takes a glob path and a password and preforms reading of all files, encryption, and writing to
-/// new files.
-/// This function will be called incase the path given is a glob surrounded by quotets (so the
-/// shell does not expand automatically).
-fn encrypt_glob(path: &str, key: &str, options: MatchOptions) -> Result<(), error::EncryptErr> {
-    let globs = counter_block::Blocks::from_clear_glob(path, key, 100, 5, options);
-    for b in globs {
-        match b {
-            (p, Ok(blocks)) => {
-                let new_path = format!("{}_enc", p);
-                blocks.into_enc_file(&new_path)?;
-            }
-            (p, Err(e)) => {
-                println!("Error in file: {}", p);
-                return Err(e);
-            }
+/// With keep_going, prints the summary of the batch, and exits with a status that tells whether
+/// all, some or none of the files failed.
+fn finish(
+    report: &report::Report,
+    keep_going: bool,
+    action: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if keep_going {
+        eprintln!("{}", report.format_summary(action));
+        if report.exit_code() != 0 {
+            exit(report.exit_code());
         }
     }
     Ok(())
 }
 
-/// Wrapper function for decrypting a single file.
-/// takes a path to a single file and a password and preforms reading of the file, decryption and
-/// writing to a new file.
-fn decrypt_single(path: &str, key: &str) -> Result<(), error::DecryptErr> {
-    let blocks = counter_block::Blocks::from_enc_file(path)?;
-    let new_path = path.replace("_enc", "");
-    blocks.into_clear_file(&key, &new_path, 0)?;
-    Ok(())
+/// Prints the header of every encrypted file matching the glob path, as text or as a json array.
+/// returns false if no file matches, or if any of them is not a valid encrypted file.
+fn print_info(path: &str, json: bool) -> Result<bool, Box<dyn std::error::Error>> {
+    let paths = file_mng::list_glob(path, MatchOptions::new())?;
+    if paths.is_empty() {
+        eprintln!("no files match {}", path);
+        return Ok(false);
+    }
+    let infos: Vec<_> = paths.iter().map(|p| (p, info::read_info(p))).collect();
+    if json {
+        let objects: Vec<String> = infos
+            .iter()
+            .map(|(p, i)| format!("  {}", info::format_json(p, i)))
+            .collect();
+        println!("[\n{}\n]", objects.join(",\n"));
+    } else {
+        for (p, i) in infos.iter() {
+            println!("{}", info::format_text(p, i));
+        }
+    }
+    Ok(infos.iter().all(|(_, i)| i.is_ok()))
 }
 
-/// Wrapper function for decrypting a glob style path.
-/// Takes a glob path and a password and preforms reading of all files, decryption, and writing to
-/// new files.
-/// This function will be called incase the path given is a glob surrounded by quotets (so the
-/// shell does not expand automatically).
-fn decrypt_glob(path: &str, key: &str, options: MatchOptions) -> Result<(), error::DecryptErr> {
-    let globs = counter_block::Blocks::from_enc_glob(path, options);
-    for b in globs {
-        match b {
-            (p, Ok(blocks)) => {
-                let new_path = p.replace("_enc", "");
-                blocks.into_clear_file(&key, &new_path, 0)?;
+/// Verifies every encrypted file matching the glob path with key, and with compare also against
+/// its original (the file decryption writes by default), and prints whether each of them passed.
+/// returns false if no file matches, or if any of them failed.
+fn verify(
+    path: &str,
+    key: &Key,
+    compare: bool,
+    suffix: &str,
+    execution: &exec::Execution,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let paths = file_mng::list_glob(path, MatchOptions::new())?;
+    if paths.is_empty() {
+        eprintln!("no files match {}", path);
+        return Ok(false);
+    }
+    let mut passed = true;
+    for p in paths.iter() {
+        let original = if compare {
+            match output::clear_path(p, suffix) {
+                Ok(o) => o.display().to_string(),
+                Err(e) => {
+                    println!("FAIL {}: {}", p, e);
+                    passed = false;
+                    continue;
+                }
             }
-            (p, Err(e)) => {
-                println!("Error in file: {}", p);
-                return Err(e);
+        } else {
+            String::new()
+        };
+        let original_path = if compare { Some(&original[..]) } else { None };
+        let res = execution.install(|| verify::verify_file(p, key, original_path));
+        let (ok, msg) = match res {
+            Ok(r) if r.key == Some(false) => (false, String::from("wrong key")),
+            Ok(r) if r.plaintext == Some(false) => {
+                (false, format!("does not decrypt to {}", original))
             }
-        }
+            Ok(r) if r.key.is_none() && r.plaintext.is_none() => (
+                true,
+                String::from("key not checked, the file has no key check value"),
+            ),
+            Ok(r) if r.plaintext == Some(true) => (true, format!("decrypts to {}", original)),
+            Ok(_) => (true, String::from("key matches")),
+            Err(e) => (false, e.to_string()),
+        };
+        println!("{} {}: {}", if ok { "PASS" } else { "FAIL" }, p, msg);
+        passed &= ok;
+    }
+    Ok(passed)
+}
+
+/// The single file matching the glob path, for reading from when writing to stdout.
+fn single_match(path: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let mut paths = file_mng::list_glob(path, MatchOptions::new())?;
+    if paths.len() != 1 {
+        return Err(Box::new(error::ArgErr::InvalidValue(format!(
+            "{} files match {}, but exactly one can be written to stdout",
+            paths.len(),
+            path
+        ))));
+    }
+    Ok(paths.remove(0))
+}
+
+/// Encrypts stdin (path "-") into the file new_path, or the file path to stdout (new_path "-").
+/// The length of stdin is not known until it ends, so the header of its file is written again
+/// then, before the file is moved to new_path. Once cancel is cancelled the file is removed.
+fn encrypt_stdio(
+    path: &str,
+    new_path: &str,
+    key: &Key,
+    block_size: usize,
+    rounds: i32,
+    force: bool,
+    cancel: &CancelToken,
+) -> Result<(), error::EncryptErr> {
+    if path == "-" {
+        let mut out = file_mng::AtomicFile::create(new_path, force)?;
+        let header = counter_block::encrypt_stream(
+            &mut io::stdin().lock(),
+            &mut out,
+            None,
+            key,
+            block_size,
+            rounds,
+            &nonce::OsNonce,
+            Some(cancel),
+        )?;
+        file_mng::rewrite_header(out.file(), &header)?;
+        out.commit()?;
+    } else {
+        let mut input = fs::File::open(path)?;
+        let len = input.metadata()?.len();
+        let mut out = io::stdout().lock();
+        counter_block::encrypt_stream(
+            &mut input,
+            &mut out,
+            Some(len),
+            key,
+            block_size,
+            rounds,
+            &nonce::OsNonce,
+            Some(cancel),
+        )?;
+        out.flush()?;
     }
     Ok(())
 }
 
-/// Wrapper function for decrypting only the first n blocks of an encrypted file.
-/// This function will most likely only be used for greping over encrypted files.
-fn decrypt_single_head(path: &str, key: &str) -> Result<(), error::DecryptErr> {
-    let blocks = counter_block::Blocks::from_enc_head(path, 100)?;
-    let new_path = path.replace("_enc", "");
-    blocks.into_clear_file(&key, &new_path, 0)?;
+/// Decrypts stdin (path "-") or the file path, into the file new_path or to stdout (new_path
+/// "-"). With head_tail, only the first or last count blocks of a file are decrypted. Once cancel
+/// is cancelled the file is removed.
+fn decrypt_stdio(
+    path: &str,
+    new_path: &str,
+    key: &Key,
+    head_tail: Option<bool>,
+    count: u64,
+    force: bool,
+    cancel: &CancelToken,
+) -> Result<(), error::DecryptErr> {
+    let cancel = Some(cancel);
+    if new_path == "-" {
+        let mut out = io::stdout().lock();
+        decrypt_to(
+            path,
+            &mut out,
+            key,
+            head_tail,
+            count,
+            &progress::ignore,
+            cancel,
+        )?;
+        out.flush()?;
+    } else {
+        let mut out = file_mng::AtomicFile::create(new_path, force)?;
+        decrypt_to(
+            path,
+            &mut out,
+            key,
+            head_tail,
+            count,
+            &progress::ignore,
+            cancel,
+        )?;
+        out.commit()?;
+    }
     Ok(())
 }
 
-/// Wrapper function for decrypting only the first n blocks of a glob of encrypted files.   
-/// This function will most likely only be used for greping over encrypted files.
-fn decrypt_glob_head(
+/// Decrypts stdin (path "-") or the file path into out. With head_tail, only the first or last
+/// count blocks of a file are decrypted. progress is called as the blocks of a whole file are
+/// decrypted, and cancel stops stdin and whole files at their next chunk.
+fn decrypt_to(
     path: &str,
-    key: &str,
-    options: MatchOptions,
+    out: &mut dyn Write,
+    key: &Key,
+    head_tail: Option<bool>,
+    count: u64,
+    progress: &progress::Progress,
+    cancel: Option<&CancelToken>,
 ) -> Result<(), error::DecryptErr> {
-    let globs = counter_block::Blocks::from_enc_glob_head(path, options, 100);
-    for b in globs {
-        match b {
-            (p, Ok(blocks)) => {
-                let new_path = p.replace("_enc", "");
-                blocks.into_clear_file(&key, &new_path, 0)?;
+    match (path, head_tail) {
+        ("-", _) => {
+            counter_block::decrypt_stream(&mut io::stdin().lock(), key, out, cancel)?;
+            Ok(())
+        }
+        (_, Some(true)) => decrypt_single_head(path, out, key, count),
+        (_, Some(false)) => decrypt_single_tail(path, out, key, count),
+        (_, None) => decrypt_single(path, out, key, progress, cancel),
+    }
+}
+
+/// Wrapper function for encrypting files.
+/// Takes the paths of the files, the path to write each of them to, and a password, and preforms
+/// reading of all files, encryption, and writing to the new files.
+/// The files are encrypted in parallel straight to disk, so memory use does not grow with the
+/// number or the size of the files. Every file appears at its output only once it is complete.
+/// With shred, every clear file is removed once its encrypted file is verified (in the thread
+/// pool of the execution), and what was removed is printed. A progress bar is drawn while the files
+/// are encrypted, if stderr is a terminal.
+/// Without options.keep_going, the first file that fails is returned as an error. Otherwise the
+/// failures (including the clear files that were kept) are collected in the returned report.
+fn encrypt_files(
+    paths: &[String],
+    outputs: &[PathBuf],
+    key: &Key,
+    block_size: usize,
+    rounds: i32,
+    options: &batch::BatchOptions,
+    shred: Option<&exec::Execution>,
+) -> Result<report::Report, error::EncryptErr> {
+    let enc_paths: HashMap<&str, String> = paths
+        .iter()
+        .zip(outputs.iter())
+        .map(|(p, o)| (&p[..], o.display().to_string()))
+        .collect();
+    let bar = progress::Bar::for_files(paths);
+    let options = batch::BatchOptions {
+        progress: &|p, bytes, _| bar.advance(p, bytes),
+        ..*options
+    };
+    let res = batch::encrypt_batch(
+        paths,
+        &|p| enc_paths[p].clone(),
+        key,
+        block_size,
+        rounds,
+        &nonce::OsNonce,
+        &options,
+    );
+    bar.finish();
+    let res = res?;
+    let mut report = report::Report::default();
+    let mut kept: usize = 0;
+    for outcome in res {
+        // nothing is shredded after ctrl-c, so every clear file is still there
+        if options.cancel.is_some_and(CancelToken::is_cancelled) {
+            return Err(error::EncryptErr::Cancelled);
+        }
+        if let Err(e) = outcome.result {
+            if !options.keep_going {
+                eprintln!("Error in file: {}", outcome.path);
+                return Err(e);
+            }
+            report.fail(&outcome.path, e);
+            continue;
+        }
+        if let Some(execution) = shred {
+            let removed =
+                execution.install(|| verify::shred_verified(&outcome.enc_path, key, &outcome.path));
+            let cause = match removed {
+                Ok(Some(len)) => {
+                    eprintln!("Removed {} ({} bytes)", outcome.path, len);
+                    None
+                }
+                Ok(None) => Some(format!("{} does not decrypt to it", outcome.enc_path)),
+                Err(e) => Some(e.to_string()),
+            };
+            if let Some(cause) = cause {
+                eprintln!("Kept {}: {}", outcome.path, cause);
+                kept += 1;
+                report.fail(&outcome.path, format!("kept, {}", cause));
+                continue;
+            }
+        }
+        report.ok();
+    }
+    if kept > 0 && !options.keep_going {
+        return Err(error::EncryptErr::IoError(format!(
+            "{} clear files were not removed",
+            kept
+        )));
+    }
+    Ok(report)
+}
+
+/// Wrapper function for decrypting files.
+/// Takes the paths of the files, the path to write each of them to, and a password, and preforms
+/// reading of every file, decryption and writing to the new file. With head_tail, only the first
+/// (Some(true)) or last (Some(false)) count blocks of every file are decrypted. Existing files
+/// are overwritten only with force.
+/// Without keep_going, the first file that fails is returned as an error. Otherwise every file is
+/// tried, and the failures are collected in the returned report. Once cancel is cancelled, the
+/// file being decrypted is removed and no other file is started. A progress bar is drawn while the
+/// files are decrypted, if stderr is a terminal.
+#[allow(clippy::too_many_arguments)]
+fn decrypt_files(
+    paths: &[String],
+    outputs: &[PathBuf],
+    key: &Key,
+    head_tail: Option<bool>,
+    count: u64,
+    force: bool,
+    keep_going: bool,
+    cancel: &CancelToken,
+) -> Result<report::Report, error::DecryptErr> {
+    let mut report = report::Report::default();
+    let bar = progress::Bar::for_files(paths);
+    for (p, o) in paths.iter().zip(outputs.iter()) {
+        if cancel.is_cancelled() {
+            bar.finish();
+            return Err(error::DecryptErr::Cancelled);
+        }
+        let progress = |bytes, _| bar.advance(p, bytes);
+        let new_path = o.display().to_string();
+        let res = file_mng::AtomicFile::create(&new_path, force)
+            .map_err(error::DecryptErr::from)
+            .and_then(|mut out| {
+                decrypt_to(p, &mut out, key, head_tail, count, &progress, Some(cancel))?;
+                Ok(out.commit()?)
+            });
+        match res {
+            Ok(()) => report.ok(),
+            Err(error::DecryptErr::Cancelled) => {
+                bar.finish();
+                return Err(error::DecryptErr::Cancelled);
             }
-            (p, Err(e)) => {
-                println!("Error in file: {}", p);
+            Err(e) if keep_going => report.fail(p, e),
+            Err(e) => {
+                bar.finish();
+                eprintln!("Error in file: {}", p);
                 return Err(e);
             }
         }
     }
+    bar.finish();
+    Ok(report)
+}
+
+/// Wrapper function for decrypting a single file.
+/// takes a path to a single file, the new file and a password and preforms reading of the file,
+/// decryption and writing to the new file. The file is decrypted in chunks, straight into the new
+/// file, so it never has to fit in memory. progress is called as the blocks are decrypted, and
+/// cancel stops it at the next chunk.
+fn decrypt_single(
+    path: &str,
+    out: &mut dyn Write,
+    key: &Key,
+    progress: &progress::Progress,
+    cancel: Option<&CancelToken>,
+) -> Result<(), error::DecryptErr> {
+    counter_block::decrypt_file_to(path, key, out, progress, cancel)?;
     Ok(())
 }
 
-/// Wrapper function for decrypting only the last n blocks of an encrypted file.
+/// Wrapper function for decrypting only the first n blocks of an encrypted file.
 /// This function will most likely only be used for greping over encrypted files.
-fn decrypt_single_tail(path: &str, key: &str) -> Result<(), error::DecryptErr> {
-    let res = counter_block::Blocks::from_enc_tail(path, 100)?;
-    let blocks = res.0;
-    let block_num = res.1;
-    let new_path = path.replace("_enc", "");
-    blocks.into_clear_file(&key, &new_path, block_num)?;
+fn decrypt_single_head(
+    path: &str,
+    out: &mut dyn Write,
+    key: &Key,
+    count: u64,
+) -> Result<(), error::DecryptErr> {
+    let blocks = counter_block::Blocks::from_enc_head(path, count)?;
+    out.write_all(&blocks.into_clear(key, 0)?)?;
     Ok(())
 }
 
-/// Wrapper function for decrypting only the last n blocks of a glob of encrypted files.   
+/// Wrapper function for decrypting only the last n blocks of an encrypted file.
 /// This function will most likely only be used for greping over encrypted files.
-fn decrypt_glob_tail(
+fn decrypt_single_tail(
     path: &str,
-    key: &str,
-    options: MatchOptions,
+    out: &mut dyn Write,
+    key: &Key,
+    count: u64,
 ) -> Result<(), error::DecryptErr> {
-    let globs = counter_block::Blocks::from_enc_glob_tail(path, options, 100);
-    for b in globs {
-        match b {
-            (p, Ok((blocks, block_num))) => {
-                let new_path = p.replace("_enc", "");
-                blocks.into_clear_file(&key, &new_path, block_num)?;
-            }
-            (p, Err(e)) => {
-                println!("Error in file: {}", p);
-                return Err(e);
-            }
-        }
-    }
+    let (blocks, block_num) = counter_block::Blocks::from_enc_tail(path, count)?;
+    out.write_all(&blocks.into_clear(key, block_num)?)?;
     Ok(())
 }
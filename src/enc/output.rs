@@ -0,0 +1,211 @@
+use common::error::ArgErr;
+use common::walk::{walk, WalkOptions};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf, MAIN_SEPARATOR};
+
+/// Where the output of every input file goes, as given with -o.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output {
+    /// next to the input, in the same directory.
+    Beside,
+    /// to this file. only one input may match.
+    File(PathBuf),
+    /// into this directory, with the name of the input.
+    Dir(PathBuf),
+}
+
+impl Output {
+    /// the output for -o o: a directory if it is one, or if it ends with a separator, and a file
+    /// otherwise.
+    pub fn new(o: Option<&str>) -> Output {
+        match o {
+            None => Output::Beside,
+            Some(o) if o.ends_with('/') || o.ends_with(MAIN_SEPARATOR) || Path::new(o).is_dir() => {
+                Output::Dir(PathBuf::from(o))
+            }
+            Some(o) => Output::File(PathBuf::from(o)),
+        }
+    }
+}
+
+/// maps every input to its output: encrypted files get suffix appended to their name, and
+/// decrypted files get it stripped from the end of theirs (and only from there).
+/// # Errors
+/// refuses mappings that are ambiguous or would lose data: a single output file for more then one
+/// input, a file to decrypt whose name does not end with suffix (unless its output is given with
+/// -o), two inputs with the same output, or an output that is one of the inputs.
+pub fn map_outputs(
+    inputs: &[String],
+    output: &Output,
+    encrypt: bool,
+    suffix: &str,
+) -> Result<Vec<PathBuf>, ArgErr> {
+    let mut outputs: Vec<PathBuf> = Vec::with_capacity(inputs.len());
+    for input in inputs.iter() {
+        let input = Path::new(input);
+        let out = match output {
+            Output::File(f) if inputs.len() == 1 => f.clone(),
+            Output::File(f) => {
+                return Err(ArgErr::InvalidValue(format!(
+                    "{} files match, but -o {} is a single file. use a directory",
+                    inputs.len(),
+                    f.display()
+                )))
+            }
+            Output::Beside => input.with_file_name(out_name(input, encrypt, suffix)?),
+            Output::Dir(d) => d.join(out_name(input, encrypt, suffix)?),
+        };
+        outputs.push(out);
+    }
+    check_outputs(inputs, &outputs)?;
+    Ok(outputs)
+}
+
+/// maps every file under root that walk_options let through to the same path under out_root,
+/// like map_outputs does with a directory. files to decrypt whose name does not end with suffix
+/// are skipped, with a message.
+pub fn map_tree(
+    root: &Path,
+    out_root: &Path,
+    walk_options: &WalkOptions,
+    encrypt: bool,
+    suffix: &str,
+) -> Result<(Vec<String>, Vec<PathBuf>), Box<dyn std::error::Error>> {
+    let mut inputs: Vec<String> = Vec::new();
+    let mut outputs: Vec<PathBuf> = Vec::new();
+    for rel in walk(root, walk_options)? {
+        let name = match out_name(&rel, encrypt, suffix) {
+            Ok(name) => name,
+            Err(_) => {
+                eprintln!("Skipping {}: not an encrypted file", rel.display());
+                continue;
+            }
+        };
+        outputs.push(out_root.join(rel.with_file_name(name)));
+        inputs.push(root.join(rel).display().to_string());
+    }
+    check_outputs(&inputs, &outputs)?;
+    Ok((inputs, outputs))
+}
+
+/// the clear file next to the encrypted file input: its path with suffix stripped from the end of
+/// its name, and only from there.
+/// # Errors
+/// returns an error if the name of input does not end with suffix.
+pub fn clear_path(input: &str, suffix: &str) -> Result<PathBuf, ArgErr> {
+    let input = Path::new(input);
+    Ok(input.with_file_name(out_name(input, false, suffix)?))
+}
+
+/// the name of the output file for input.
+fn out_name(input: &Path, encrypt: bool, suffix: &str) -> Result<String, ArgErr> {
+    let name = match input.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => {
+            return Err(ArgErr::InvalidValue(format!(
+                "{} is not a file",
+                input.display()
+            )))
+        }
+    };
+    if encrypt {
+        return Ok(format!("{}{}", name, suffix));
+    }
+    match name.strip_suffix(suffix) {
+        Some(n) if !n.is_empty() && !suffix.is_empty() => Ok(String::from(n)),
+        _ => Err(ArgErr::InvalidValue(format!(
+            "the name of {} does not end with {}, so the name of its clear file is unknown. \
+             give it with -o",
+            input.display(),
+            suffix
+        ))),
+    }
+}
+
+/// refuses two inputs with the same output, and outputs that overwrite an input. paths are
+/// compared after normalize, so `./a` and `a` are the same file.
+fn check_outputs(inputs: &[String], outputs: &[PathBuf]) -> Result<(), ArgErr> {
+    let mut seen: HashMap<PathBuf, &str> = HashMap::new();
+    for input in inputs.iter() {
+        seen.insert(normalize(Path::new(input)), input);
+    }
+    for (input, out) in inputs.iter().zip(outputs.iter()) {
+        let key = normalize(out);
+        if let Some(other) = seen.insert(key.clone(), input) {
+            return Err(ArgErr::InvalidValue(
+                if normalize(Path::new(other)) == key {
+                    format!("{} would overwrite an input", out.display())
+                } else {
+                    format!(
+                        "both {} and {} would be written to {}",
+                        other,
+                        input,
+                        out.display()
+                    )
+                },
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// path without its `.` components and redundant separators. `..` is kept, since it can not be
+/// resolved without looking at the file system (the directory before it may be a link).
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| *c != Component::CurDir)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| String::from(*s)).collect()
+    }
+
+    fn paths(v: Vec<PathBuf>) -> Vec<String> {
+        v.iter().map(|p| p.display().to_string()).collect()
+    }
+
+    #[test]
+    fn suffix_only_at_the_end() {
+        let inputs = strings(&["/data/raw_encodings/x_enc", "y_enc_enc"]);
+        let res = map_outputs(&inputs, &Output::Beside, false, "_enc").unwrap();
+        assert_eq!(paths(res), vec!["/data/raw_encodings/x", "y_enc"]);
+        let res = map_outputs(&inputs, &Output::Dir(PathBuf::from("out")), true, ".br").unwrap();
+        assert_eq!(paths(res), vec!["out/x_enc.br", "out/y_enc_enc.br"]);
+    }
+
+    #[test]
+    fn clear_paths() {
+        let res = clear_path("/data/raw_encodings/x_enc", "_enc").unwrap();
+        assert_eq!(res, PathBuf::from("/data/raw_encodings/x"));
+        let res = clear_path("a_enc/x_enc.br", ".br").unwrap();
+        assert_eq!(res, PathBuf::from("a_enc/x_enc"));
+        assert!(clear_path("a/x_enc", ".br").is_err());
+    }
+
+    #[test]
+    fn ambiguous_mappings() {
+        let inputs = strings(&["a/x_enc", "b/x_enc"]);
+        // same name in two directories
+        assert!(map_outputs(&inputs, &Output::Dir(PathBuf::from("out")), false, "_enc").is_err());
+        // one file for two inputs
+        assert!(map_outputs(&inputs, &Output::File(PathBuf::from("f")), false, "_enc").is_err());
+        // no suffix to strip, unless the output is given
+        let inputs = strings(&["a/x"]);
+        assert!(map_outputs(&inputs, &Output::Beside, false, "_enc").is_err());
+        assert!(map_outputs(&inputs, &Output::File(PathBuf::from("y")), false, "_enc").is_ok());
+        // an output that is an input
+        assert!(map_outputs(&inputs, &Output::Beside, true, "").is_err());
+        let inputs = strings(&["a/x", "a/x_enc"]);
+        assert!(map_outputs(&inputs, &Output::Beside, true, "_enc").is_err());
+        // the same files, spelled differently
+        let inputs = strings(&["./a/x", "a//x_enc"]);
+        assert!(map_outputs(&inputs, &Output::Beside, true, "_enc").is_err());
+        let inputs = strings(&["a/x", "./a/x"]);
+        assert!(map_outputs(&inputs, &Output::Beside, true, "_enc").is_err());
+    }
+}
@@ -1,119 +1,644 @@
+use crate::report;
+use common::defaults;
 use common::error::ArgErr;
+use common::password::KeySource;
+use common::walk::{Symlinks, WalkOptions};
+use glob::Pattern;
+use std::collections::HashMap;
+use zeroize::Zeroizing;
+
+/// the commands of brenc. every command has its own options (see Command::accepts).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Encrypt,
+    Decrypt,
+    /// decrypt to stdout.
+    Cat,
+    Info,
+    Verify,
+    Grep,
+}
+
+impl Command {
+    const ALL: [Command; 6] = [
+        Command::Encrypt,
+        Command::Decrypt,
+        Command::Cat,
+        Command::Info,
+        Command::Verify,
+        Command::Grep,
+    ];
+
+    /// the name of the command on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Encrypt => "encrypt",
+            Command::Decrypt => "decrypt",
+            Command::Cat => "cat",
+            Command::Info => "info",
+            Command::Verify => "verify",
+            Command::Grep => "grep",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Command> {
+        Command::ALL.iter().copied().find(|c| c.name() == name)
+    }
+
+    /// whether flag (in its long form, see long_flag) is an option of the command.
+    fn accepts(&self, flag: &str) -> bool {
+        const KEY: [&str; 4] = ["--key", "--key-env", "--key-file", "--key-fd"];
+        const WALK: [&str; 4] = ["--recursive", "--include", "--exclude", "--symlinks"];
+        const PART: [&str; 3] = ["--head", "--tail", "--count"];
+        let own: &[&str] = match self {
+            Command::Encrypt => &[
+                "--output",
+                "--suffix",
+                "--block-size",
+                "--rounds",
+                "--threads",
+                "--force",
+                "--shred",
+                "--keep-going",
+            ],
+            Command::Decrypt => &[
+                "--output",
+                "--suffix",
+                "--threads",
+                "--force",
+                "--keep-going",
+            ],
+            Command::Cat | Command::Grep => &["--threads"],
+            Command::Info => &["--json"],
+            Command::Verify => &["--compare", "--suffix", "--threads"],
+        };
+        own.contains(&flag)
+            || (*self != Command::Info && KEY.contains(&flag))
+            || (matches!(self, Command::Encrypt | Command::Decrypt) && WALK.contains(&flag))
+            || (matches!(self, Command::Decrypt | Command::Cat | Command::Grep)
+                && PART.contains(&flag))
+    }
+}
 
 #[derive(Debug)]
 /// holds the parsed arguments. it is passed to the main function to determine the proper action.
-/// some of the varients old a string with a prarmeter given with the argument (eg. -f
+/// some of the varients old a string with a prarmeter given with the argument (eg. -o
 /// /path/to/file).
 pub enum Args {
-    Key(String),
+    /// print the help of the command (or of brenc, for None) and exit.
+    Help(Option<Command>),
+    /// print the version and exit.
+    Version,
+    /// where the key comes from: -k, --key-env, --key-file or --key-fd. without any of them, the
+    /// key is prompted for.
+    Key(KeySource),
     Encrypt(String),
     Decrypt(String),
+    /// brenc cat: decrypt the files at the path to stdout.
+    Cat(String),
+    /// brenc info: print the headers of the encrypted files at the path.
+    Info(String),
+    /// print info as json.
+    Json,
+    /// brenc verify: check the encrypted files at the path.
+    Verify(String),
+    /// compare the verified files with their originals.
+    Compare,
+    /// brenc grep: search the encrypted files at the path (the second string) for the
+    /// expression (the first one).
+    Grep(String, String),
     Head,
     Tail,
+    BlockSize(usize),
+    Rounds(i32),
+    Count(u64),
+    Threads(usize),
+    /// the path is a directory, encrypt or decrypt every file under it.
+    Recursive,
+    /// the output file or directory, or the root of the output tree for -R.
+    Output(String),
+    /// the suffix of encrypted file names.
+    Suffix(String),
+    Include(Pattern),
+    Exclude(Pattern),
+    Symlinks(Symlinks),
+    /// overwrite output files that already exist.
+    Force,
+    /// remove every clear file once its encrypted file is verified.
+    Shred,
+    /// go on with the other files when one fails, and print a summary of the failures.
+    KeepGoing,
 }
 
-///parse the vector of arguments passed from main into a vector of tuples that look like:
-/// (<type of argument>, <value>)
+///parse the vector of arguments passed from main into a vector of Args.
+///
+/// the first argument is the command (encrypt, decrypt, cat, info, verify or grep), followed by
+/// the options of the command and its path (grep takes an expression before the path). options
+/// have a long form ("--output <path>" or "--output=<path>") and most have a short one ("-o
+/// <path>"); "--" ends the options, for paths that start with "-". "--help" prints the help of
+/// the command, and "--version" the version.
 ///
-/// types are:
-/// - "-e" for encryption
-/// - "-d" for decryption
-/// - "-k" for the key
+/// the older form, with "-e <path>" or "-d <path>" instead of a command, is still accepted.
 ///
 /// # Errors
-/// will print a usege message if:
-/// - amount of arguments is wrong
-/// - a double argument
-/// - unrecognized argument
-/// and then returns an Err(()), which will cause main to exit.
-pub fn parse_args(mut argv: Vec<String>) -> Result<Vec<Args>, ArgErr> {
-    //TODO: implement Args enum, write exapmle code in doc and deal with incorect number of
-    //arguments
+/// returns an ArgErr telling what is wrong if:
+/// - an argument is missing, or an option has no value
+/// - an argument is given twice, or two arguments conflict
+/// - an argument is unknown, or not an option of the command
+/// - a value is out of range
+pub fn parse_args(argv: Vec<String>) -> Result<Vec<Args>, ArgErr> {
+    // a key given with -k is one of the arguments, so all of them are wiped once parsed
+    let mut argv = Zeroizing::new(argv);
     argv.remove(0);
-    let argv_iter = argv.iter().enumerate();
+    let (command, rest) = match argv.first().map(String::as_str) {
+        None => return Err(ArgErr::MissingArg(String::from("a command"))),
+        Some("--help") | Some("-h") | Some("help") => {
+            return match argv.get(1) {
+                None => Ok(vec![Args::Help(None)]),
+                Some(c) => match Command::from_name(c) {
+                    Some(c) => Ok(vec![Args::Help(Some(c))]),
+                    None => Err(ArgErr::UnknownArg(format!("command {}", c))),
+                },
+            }
+        }
+        Some("--version") | Some("-V") => return Ok(vec![Args::Version]),
+        Some(c) => match Command::from_name(c) {
+            Some(c) => (Some(c), &argv[1..]),
+            // the older form, the command is given with -e / -d
+            None if c.starts_with('-') => (None, &argv[..]),
+            None => return Err(ArgErr::UnknownArg(format!("command {}", c))),
+        },
+    };
+
     let mut final_args: Vec<Args> = Vec::new();
-    let mut is_param: bool = false;
-    for (index, arg) in argv_iter {
-        if is_param {
-            is_param = false;
+    let mut positional: Vec<String> = Vec::new();
+    // how many times every option was given, by its long form
+    let mut given: HashMap<&'static str, u8> = HashMap::new();
+    let mut index = 0;
+    while index < rest.len() {
+        let arg = &rest[index];
+        index += 1;
+        if arg == "--" {
+            positional.extend(rest[index..].iter().cloned());
+            break;
+        }
+        if arg == "-" || !arg.starts_with('-') {
+            positional.push(arg.clone());
             continue;
         }
-        match &arg[..] {
-            "-e" => {
-                final_args.push(Args::Encrypt(String::from(&argv[index + 1])));
-                is_param = true;
+        let (flag, mut inline) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => (&arg[..i], Some(String::from(&arg[i + 1..]))),
+            _ => (&arg[..], None),
+        };
+        let flag = match long_flag(flag) {
+            Some(f) => f,
+            None => return Err(ArgErr::UnknownArg(String::from(arg))),
+        };
+        let mut value = || -> Result<String, ArgErr> {
+            if let Some(v) = inline.take() {
+                return Ok(v);
             }
-            "-d" => {
-                final_args.push(Args::Decrypt(String::from(&argv[index + 1])));
-                is_param = true;
+            // an option is never taken as the value of the one before it, values starting with '-'
+            // are given as --flag=value
+            match rest.get(index) {
+                Some(v) if v == "-" || !v.starts_with('-') => {
+                    index += 1;
+                    Ok(v.clone())
+                }
+                _ => Err(ArgErr::MissingArg(format!("the value of {}", flag))),
             }
-            "-k" => {
-                final_args.push(Args::Key(String::from(&argv[index + 1])));
-                is_param = true;
-            }
-            "-head" => {
-                final_args.push(Args::Head);
-            }
-            "-tail" => {
-                final_args.push(Args::Tail);
+        };
+        let parsed = match flag {
+            "--help" => return Ok(vec![Args::Help(command)]),
+            "--version" => return Ok(vec![Args::Version]),
+            "--encrypt" if command.is_none() => Args::Encrypt(value()?),
+            "--decrypt" if command.is_none() => Args::Decrypt(value()?),
+            "--key" | "--key-env" | "--key-file" | "--key-fd" => {
+                Args::Key(KeySource::parse(flag, value()?)?)
             }
+            "--block-size" => Args::BlockSize(parse_block_size(&value()?)?),
+            "--rounds" => Args::Rounds(parse_rounds(&value()?)?),
+            "--count" => Args::Count(parse_count(&value()?)?),
+            "--threads" => Args::Threads(parse_threads(&value()?)?),
+            "--output" => Args::Output(value()?),
+            "--suffix" => Args::Suffix(value()?),
+            "--include" => Args::Include(parse_pattern(&value()?)?),
+            "--exclude" => Args::Exclude(parse_pattern(&value()?)?),
+            "--symlinks" => Args::Symlinks(parse_symlinks(&value()?)?),
+            "--recursive" => Args::Recursive,
+            "--head" => Args::Head,
+            "--tail" => Args::Tail,
+            "--force" => Args::Force,
+            "--shred" => Args::Shred,
+            "--keep-going" => Args::KeepGoing,
+            "--json" => Args::Json,
+            "--compare" => Args::Compare,
             _ => {
-                print_usege();
-                return Err(ArgErr::UnknownArg(String::from(arg)));
+                return Err(ArgErr::ArgMismatch(format!(
+                    "{} can only be used without a command",
+                    arg
+                )))
             }
+        };
+        if inline.is_some() {
+            return Err(ArgErr::InvalidValue(format!("{} takes no value", flag)));
         }
+        *given.entry(flag).or_insert(0) += 1;
+        final_args.push(parsed);
     }
 
-    validate_input(&final_args)?;
+    let command = match command {
+        Some(c) => {
+            final_args.insert(0, command_arg(c, positional)?);
+            c
+        }
+        None => {
+            if let Some(p) = positional.first() {
+                return Err(ArgErr::UnknownArg(p.clone()));
+            }
+            match (given.get("--encrypt"), given.get("--decrypt")) {
+                (Some(1), None) => Command::Encrypt,
+                (None, Some(1)) => Command::Decrypt,
+                _ => {
+                    return Err(ArgErr::ArgMismatch(String::from(
+                        "give exactly one of -e and -d, or a command",
+                    )))
+                }
+            }
+        }
+    };
+    validate_input(command, &final_args, &given)?;
     Ok(final_args)
 }
 
-/// validate that the correct number of arguments was given, and that no more then one argument of
-/// each kind is present.
-fn validate_input(v: &[Args]) -> Result<(), ArgErr> {
-    let mut enc: u8 = 0;
-    let mut dec: u8 = 0;
-    let mut key: u8 = 0;
-    let mut head: u8 = 0;
-    let mut tail: u8 = 0;
-    for arg in v.iter() {
-        match arg {
-            Args::Encrypt(_) => enc += 1,
-            Args::Decrypt(_) => dec += 1,
-            Args::Key(_) => key += 1,
-            Args::Head => head += 1,
-            Args::Tail => tail += 1,
-        }
-    }
-    if (enc + dec) != 1 {
-        print_usege();
-        return Err(ArgErr::ArgMismatch);
-    } else if key != 1 {
-        print_usege();
-        return Err(ArgErr::MissingArg);
-    } else if (head + tail) > 1 {
-        print_usege();
-        return Err(ArgErr::ArgMismatch);
-    }
-    Ok(())
-}
-
-/// prints the usege message
-fn print_usege() {
-    println!(
-        "usege:
-            broken <flag> <path> <-k> <key>
-            flags:
-            -e => encrypt
-            -d => decrypt
-            "
+/// the long form of flag, or None if it is not an option of brenc. -e and -d are the commands of
+/// the older form, and -head and -tail their older spelling.
+fn long_flag(flag: &str) -> Option<&'static str> {
+    const LONG: [&str; 25] = [
+        "--help",
+        "--version",
+        "--key",
+        "--key-env",
+        "--key-file",
+        "--key-fd",
+        "--output",
+        "--suffix",
+        "--block-size",
+        "--rounds",
+        "--count",
+        "--threads",
+        "--head",
+        "--tail",
+        "--recursive",
+        "--include",
+        "--exclude",
+        "--symlinks",
+        "--force",
+        "--shred",
+        "--keep-going",
+        "--json",
+        "--compare",
+        "--encrypt",
+        "--decrypt",
+    ];
+    let long = match flag {
+        "-h" => "--help",
+        "-V" => "--version",
+        "-k" => "--key",
+        "-o" => "--output",
+        "-b" => "--block-size",
+        "-r" => "--rounds",
+        "-n" => "--count",
+        "-R" => "--recursive",
+        "-head" => "--head",
+        "-tail" => "--tail",
+        "-e" => "--encrypt",
+        "-d" => "--decrypt",
+        f => f,
+    };
+    LONG.iter().copied().find(|l| *l == long)
+}
+
+/// the argument of command, made of its positional arguments: the path (for grep, the expression
+/// and the path).
+fn command_arg(command: Command, mut positional: Vec<String>) -> Result<Args, ArgErr> {
+    let wanted = if command == Command::Grep { 2 } else { 1 };
+    if positional.len() < wanted {
+        return Err(ArgErr::MissingArg(String::from(
+            if positional.is_empty() && command == Command::Grep {
+                "the expression to search for"
+            } else {
+                "the path of the files"
+            },
+        )));
+    }
+    if positional.len() > wanted {
+        return Err(ArgErr::UnknownArg(format!(
+            "{} (give a single path, and quote globs so the shell does not expand them)",
+            positional[wanted]
+        )));
+    }
+    let path = positional.pop().unwrap_or_default();
+    Ok(match command {
+        Command::Encrypt => Args::Encrypt(path),
+        Command::Decrypt => Args::Decrypt(path),
+        Command::Cat => Args::Cat(path),
+        Command::Info => Args::Info(path),
+        Command::Verify => Args::Verify(path),
+        Command::Grep => Args::Grep(positional.pop().unwrap_or_default(), path),
+    })
+}
+
+/// parses the block size, which has to be between defaults::MIN_BLOCK_SIZE and
+/// defaults::MAX_BLOCK_SIZE.
+fn parse_block_size(v: &str) -> Result<usize, ArgErr> {
+    match v.parse::<usize>() {
+        Ok(n) if (defaults::MIN_BLOCK_SIZE..=defaults::MAX_BLOCK_SIZE).contains(&n) => Ok(n),
+        _ => invalid(format!(
+            "block size should be a number between {} and {}, got {}",
+            defaults::MIN_BLOCK_SIZE,
+            defaults::MAX_BLOCK_SIZE,
+            v
+        )),
+    }
+}
+
+/// parses the number of rounds, which has to be between defaults::MIN_ROUNDS and
+/// defaults::MAX_ROUNDS.
+fn parse_rounds(v: &str) -> Result<i32, ArgErr> {
+    match v.parse::<i32>() {
+        Ok(n) if (defaults::MIN_ROUNDS..=defaults::MAX_ROUNDS).contains(&n) => Ok(n),
+        _ => invalid(format!(
+            "rounds should be a number between {} and {}, got {}",
+            defaults::MIN_ROUNDS,
+            defaults::MAX_ROUNDS,
+            v
+        )),
+    }
+}
+
+/// parses the number of blocks for --head / --tail, which has to be positive.
+fn parse_count(v: &str) -> Result<u64, ArgErr> {
+    match v.parse::<u64>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => invalid(format!("number of blocks should be positive, got {}", v)),
+    }
+}
+
+/// parses the number of threads, which has to be positive.
+fn parse_threads(v: &str) -> Result<usize, ArgErr> {
+    match v.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => invalid(format!("number of threads should be positive, got {}", v)),
+    }
+}
+
+/// parses an include / exclude pattern.
+fn parse_pattern(v: &str) -> Result<Pattern, ArgErr> {
+    match WalkOptions::pattern(v) {
+        Ok(p) => Ok(p),
+        Err(e) => invalid(format!("invalid pattern {}: {}", v, e)),
+    }
+}
+
+/// parses the symlink policy, "skip" or "follow".
+fn parse_symlinks(v: &str) -> Result<Symlinks, ArgErr> {
+    match v {
+        "skip" => Ok(Symlinks::Skip),
+        "follow" => Ok(Symlinks::Follow),
+        _ => invalid(format!("symlinks should be skip or follow, got {}", v)),
+    }
+}
+
+fn invalid<T>(msg: String) -> Result<T, ArgErr> {
+    Err(ArgErr::InvalidValue(msg))
+}
+
+fn mismatch(msg: &str) -> Result<(), ArgErr> {
+    Err(ArgErr::ArgMismatch(String::from(msg)))
+}
+
+/// validate that every option given is an option of command, that no more then one argument of
+/// each kind is present, and that the arguments do not conflict.
+fn validate_input(
+    command: Command,
+    v: &[Args],
+    given: &HashMap<&'static str, u8>,
+) -> Result<(), ArgErr> {
+    let mut flags: Vec<&'static str> = given.keys().copied().collect();
+    flags.sort_unstable();
+    for flag in flags {
+        if flag == "--encrypt" || flag == "--decrypt" {
+            continue;
+        }
+        if !command.accepts(flag) {
+            return Err(ArgErr::ArgMismatch(format!(
+                "{} is not an option of brenc {}",
+                flag,
+                command.name()
+            )));
+        }
+        if given[flag] > 1 && flag != "--include" && flag != "--exclude" {
+            return Err(ArgErr::ArgMismatch(format!(
+                "{} is given more then once",
+                flag
+            )));
+        }
+    }
+    let n = |flag: &str| given.get(flag).copied().unwrap_or(0);
+    let keys = n("--key") + n("--key-env") + n("--key-file") + n("--key-fd");
+    let head_tail = n("--head") + n("--tail");
+    let walk_options = n("--include") + n("--exclude") + n("--symlinks");
+    let path = v.iter().find_map(|a| match a {
+        Args::Encrypt(p) | Args::Decrypt(p) | Args::Cat(p) | Args::Grep(_, p) => Some(&p[..]),
+        _ => None,
+    });
+    let output = v.iter().find_map(|a| match a {
+        Args::Output(p) => Some(&p[..]),
+        _ => None,
+    });
+    let stdin = path == Some("-");
+    let stdout = output == Some("-");
+
+    if keys > 1 {
+        mismatch("give only one of --key, --key-env, --key-file and --key-fd")
+    } else if head_tail > 1 {
+        mismatch("--head and --tail can not be used together")
+    } else if n("--count") == 1 && head_tail == 0 {
+        mismatch("--count needs --head or --tail")
+    } else if n("--recursive") > n("--output") {
+        mismatch("--recursive needs --output, the root of the tree to write the files to")
+    } else if walk_options > 0 && n("--recursive") == 0 {
+        mismatch("--include, --exclude and --symlinks only work with --recursive")
+    } else if n("--recursive") == 1 && head_tail > 0 {
+        mismatch("--recursive can not be used with --head or --tail")
+    } else if stdin && command == Command::Grep {
+        mismatch("grep needs files, it can not read stdin")
+    } else if stdin && head_tail > 0 {
+        mismatch("--head and --tail need a file, they can not read stdin")
+    } else if stdin && output.is_none() && command != Command::Cat {
+        // stdin has no name to write next to
+        mismatch("reading stdin needs --output")
+    } else if stdin && command == Command::Encrypt && stdout {
+        // the header of a stream encrypted from stdin is rewritten once its length is known
+        mismatch("encrypting stdin needs --output to be a file, not stdout")
+    } else if (stdin || stdout) && (n("--recursive") + n("--shred") + n("--keep-going")) > 0 {
+        mismatch("stdin and stdout can not be used with --recursive, --shred or --keep-going")
+    } else {
+        Ok(())
+    }
+}
+
+/// the help of command, or of brenc for None.
+pub fn usage(command: Option<Command>) -> String {
+    const KEY: &str = "
+key (prompted for, without echo, if none is given):
+    -k, --key <key>             the key itself (it is left in shell history and ps)
+    --key-env <var>             read the key from an environment variable
+    --key-file <path>           read the key from a file (a trailing newline is dropped)
+    --key-fd <n>                read the key from the first line of an open file descriptor";
+    const THREADS: &str = "
+    --threads <n>               number of threads to use (default: one per cpu)";
+    const WALK: &str = "
+    -R, --recursive             the path is a directory: every file under it, into the tree
+                                given with --output
+    --include <pattern>         with -R, only the files whose path under the directory matches
+                                (can be given more then once)
+    --exclude <pattern>         with -R, leave out the files and directories whose path matches
+                                (can be given more then once)
+    --symlinks <skip|follow>    with -R, what to do with symbolic links (default skip)";
+    let part = format!(
+        "
+    --head / --tail             only the first / last blocks of every file
+    -n, --count <blocks>        number of blocks for --head / --tail (default {})",
+        defaults::HEAD_TAIL_BLOCKS
+    );
+    let output = format!(
+        "
+    -o, --output <path>         the file to write to (- for stdout), or the directory to write
+                                the files to if it is one (or ends with /)
+    --suffix <suffix>           added to the names of encrypted files, and stripped from the end
+                                of the names of decrypted ones (default {})
+    --force                     overwrite output files that already exist
+    --keep-going                when a file fails, go on with the others, and print a table of
+                                the files that failed and why (exits with {} if only some of
+                                the files failed)",
+        defaults::ENC_SUFFIX,
+        report::EXIT_PARTIAL
     );
+    match command {
+        None => format!(
+            "brenc: encrypt and decrypt files
+
+usage: brenc <command> [options]
+
+commands:
+    encrypt <path>              encrypt the files at path (a file, a glob or - for stdin)
+    decrypt <path>              decrypt the files at path
+    cat <path>                  decrypt the files at path to stdout
+    info <path>                 print how the files at path were encrypted
+    verify <path>               check the files at path against the key and their originals
+    grep <expression> <path>    search the files at path without writing their clear text
+
+options:
+    -h, --help                  print this help (brenc <command> --help for a command)
+    -V, --version               print the version
+
+exit status: 0 on success, 1 on failure, 2 for wrong arguments, {} when only some of the files
+failed (with --keep-going), and {} when stopped by ctrl-c (encrypt and decrypt remove the files
+they did not finish).
+
+the older form, brenc -e <path> / -d <path> [options], is still accepted.",
+            report::EXIT_PARTIAL,
+            report::EXIT_CANCELLED
+        ),
+        Some(Command::Encrypt) => format!(
+            "usage: brenc encrypt <path> [options]
+
+encrypts the files at path (a file, a glob or - for stdin) next to them, or into --output.
+{}
+
+options:{}
+    -b, --block-size <bytes>    block size (default {})
+    -r, --rounds <rounds>       feistel rounds (default {}, {} to {}){}{}
+    --shred                     after encrypting, check that every encrypted file decrypts to
+                                its clear file, and only then overwrite the clear file with
+                                zeros and remove it",
+            KEY,
+            output,
+            defaults::BLOCK_SIZE,
+            defaults::ROUNDS,
+            defaults::MIN_ROUNDS,
+            defaults::MAX_ROUNDS,
+            THREADS,
+            WALK
+        ),
+        Some(Command::Decrypt) => format!(
+            "usage: brenc decrypt <path> [options]
+
+decrypts the files at path (a file, a glob or - for stdin) next to them, or into --output.
+{}
+
+options:{}{}{}{}",
+            KEY, output, part, THREADS, WALK
+        ),
+        Some(Command::Cat) => format!(
+            "usage: brenc cat <path> [options]
+
+decrypts the files at path (a file, a glob or - for stdin) to stdout, one after the other.
+{}
+
+options:{}{}",
+            KEY, part, THREADS
+        ),
+        Some(Command::Info) => String::from(
+            "usage: brenc info <path> [--json]
+
+prints how the files at path were encrypted. only the headers are read, so no key is needed.
+
+options:
+    --json                      print a json array, for other programs to read",
+        ),
+        Some(Command::Verify) => format!(
+            "usage: brenc verify <path> [options]
+
+checks the files at path: their headers, whether the key is the one they were encrypted with,
+and with --compare that they decrypt to their originals.
+{}
+
+options:
+    --compare                   compare every file with its original, the file decryption
+                                would write
+    --suffix <suffix>           the suffix stripped to find the originals (default {}){}",
+            KEY,
+            defaults::ENC_SUFFIX,
+            THREADS
+        ),
+        Some(Command::Grep) => format!(
+            "usage: brenc grep <expression> <path> [options]
+
+searches the files at path for the regular expression, and prints the matching lines. the files
+are decrypted in memory, so their clear text is never written to disk.
+{}
+
+options:{}{}",
+            KEY, part, THREADS
+        ),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::parse_args;
     use common::error::ArgErr;
+    use common::password::KeySource;
+
+    fn args(v: &[&str]) -> Result<Vec<parse_args::Args>, ArgErr> {
+        let mut argv = vec![String::from("path/to/exctuable/")];
+        argv.extend(v.iter().map(|s| String::from(*s)));
+        parse_args::parse_args(argv)
+    }
+
     #[test]
     fn missing_f_name() -> Result<(), String> {
         let args: Vec<String> = vec![
@@ -124,9 +649,9 @@ mod tests {
         ];
         let parsed = parse_args::parse_args(args);
         match parsed {
-            Err(ArgErr::UnknownArg(_)) => Ok(()),
+            Err(ArgErr::MissingArg(_)) => Ok(()),
             _ => Err(format!(
-                "should preduce ArgErr::UnknownArg, but preduced {:?}",
+                "should preduce ArgErr::MissingArg, but preduced {:?}",
                 parsed
             )),
         }
@@ -143,7 +668,7 @@ mod tests {
         ];
         let parsed = parse_args::parse_args(args);
         match parsed {
-            Err(ArgErr::ArgMismatch) => Ok(()),
+            Err(ArgErr::ArgMismatch(_)) => Ok(()),
             _ => Err(format!(
                 "should preduce ArgErr::ArgMismatch, but preduced {:?}",
                 parsed
@@ -162,11 +687,356 @@ mod tests {
         ];
         let parsed = parse_args::parse_args(args);
         match parsed {
-            Err(ArgErr::ArgMismatch) => Ok(()),
+            Err(ArgErr::ArgMismatch(_)) => Ok(()),
             _ => Err(format!(
                 "should preduce ArgErr::ArgMismatch, but preduced {:?}",
                 parsed
             )),
         }
     }
+
+    #[test]
+    fn rounds_too_low() -> Result<(), String> {
+        let args: Vec<String> = vec![
+            String::from("path/to/exctuable/"),
+            String::from("-e"),
+            String::from("bla/bla"),
+            String::from("-k"),
+            String::from("suprsecret"),
+            String::from("-r"),
+            String::from("2"),
+        ];
+        let parsed = parse_args::parse_args(args);
+        match parsed {
+            Err(ArgErr::InvalidValue(_)) => Ok(()),
+            _ => Err(format!(
+                "should preduce ArgErr::InvalidValue, but preduced {:?}",
+                parsed
+            )),
+        }
+    }
+
+    #[test]
+    fn block_size_too_big() -> Result<(), String> {
+        let args: Vec<String> = vec![
+            String::from("path/to/exctuable/"),
+            String::from("-e"),
+            String::from("bla/bla"),
+            String::from("-k"),
+            String::from("suprsecret"),
+            String::from("-b"),
+            String::from("99999999999"),
+        ];
+        let parsed = parse_args::parse_args(args);
+        match parsed {
+            Err(ArgErr::InvalidValue(_)) => Ok(()),
+            _ => Err(format!(
+                "should preduce ArgErr::InvalidValue, but preduced {:?}",
+                parsed
+            )),
+        }
+    }
+
+    #[test]
+    fn count_without_head() -> Result<(), String> {
+        let args: Vec<String> = vec![
+            String::from("path/to/exctuable/"),
+            String::from("-d"),
+            String::from("bla/bla"),
+            String::from("-k"),
+            String::from("suprsecret"),
+            String::from("-n"),
+            String::from("10"),
+        ];
+        let parsed = parse_args::parse_args(args);
+        match parsed {
+            Err(ArgErr::ArgMismatch(_)) => Ok(()),
+            _ => Err(format!(
+                "should preduce ArgErr::ArgMismatch, but preduced {:?}",
+                parsed
+            )),
+        }
+    }
+
+    #[test]
+    fn zero_threads() -> Result<(), String> {
+        let args: Vec<String> = vec![
+            String::from("path/to/exctuable/"),
+            String::from("-e"),
+            String::from("bla/bla"),
+            String::from("-k"),
+            String::from("suprsecret"),
+            String::from("--threads"),
+            String::from("0"),
+        ];
+        let parsed = parse_args::parse_args(args);
+        match parsed {
+            Err(ArgErr::InvalidValue(_)) => Ok(()),
+            _ => Err(format!(
+                "should preduce ArgErr::InvalidValue, but preduced {:?}",
+                parsed
+            )),
+        }
+    }
+
+    #[test]
+    fn info_args() -> Result<(), String> {
+        let args: Vec<String> = vec![
+            String::from("path/to/exctuable/"),
+            String::from("info"),
+            String::from("--json"),
+            String::from("bla/*_enc"),
+        ];
+        match parse_args::parse_args(args) {
+            Ok(ref v) if matches!(v[..], [parse_args::Args::Info(ref p), parse_args::Args::Json] if p == "bla/*_enc") => {
+                Ok(())
+            }
+            parsed => Err(format!(
+                "should parse info with json, but preduced {:?}",
+                parsed
+            )),
+        }
+    }
+
+    #[test]
+    fn info_without_path() -> Result<(), String> {
+        let args: Vec<String> = vec![
+            String::from("path/to/exctuable/"),
+            String::from("info"),
+            String::from("--json"),
+        ];
+        let parsed = parse_args::parse_args(args);
+        match parsed {
+            Err(ArgErr::MissingArg(_)) => Ok(()),
+            _ => Err(format!(
+                "should preduce ArgErr::MissingArg, but preduced {:?}",
+                parsed
+            )),
+        }
+    }
+
+    #[test]
+    fn verify_two_keys() -> Result<(), String> {
+        let args: Vec<String> = vec![
+            String::from("path/to/exctuable/"),
+            String::from("verify"),
+            String::from("bla/bla_enc"),
+            String::from("--key-env"),
+            String::from("BROKEN_KEY"),
+            String::from("--key-fd"),
+            String::from("3"),
+        ];
+        let parsed = parse_args::parse_args(args);
+        match parsed {
+            Err(ArgErr::ArgMismatch(_)) => Ok(()),
+            _ => Err(format!(
+                "should preduce ArgErr::ArgMismatch, but preduced {:?}",
+                parsed
+            )),
+        }
+    }
+
+    #[test]
+    fn key_sources() {
+        // without a key source, the key is prompted for
+        assert!(args(&["-e", "bla"]).is_ok());
+        assert!(matches!(
+            args(&["-e", "bla", "--key-file", "k"]).unwrap()[1],
+            parse_args::Args::Key(KeySource::File(_))
+        ));
+        assert!(matches!(
+            args(&["-e", "bla", "--key-fd=-1"]),
+            Err(ArgErr::InvalidValue(_))
+        ));
+        assert!(matches!(
+            args(&["-e", "bla", "-k", "pw", "--key-env", "V"]),
+            Err(ArgErr::ArgMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn recursive_without_output() -> Result<(), String> {
+        let args: Vec<String> = vec![
+            String::from("path/to/exctuable/"),
+            String::from("-e"),
+            String::from("bla/"),
+            String::from("-k"),
+            String::from("suprsecret"),
+            String::from("-R"),
+            String::from("--exclude"),
+            String::from("*.log"),
+        ];
+        let parsed = parse_args::parse_args(args);
+        match parsed {
+            Err(ArgErr::ArgMismatch(_)) => Ok(()),
+            _ => Err(format!(
+                "should preduce ArgErr::ArgMismatch, but preduced {:?}",
+                parsed
+            )),
+        }
+    }
+
+    #[test]
+    fn force_twice() -> Result<(), String> {
+        let args: Vec<String> = vec![
+            String::from("path/to/exctuable/"),
+            String::from("-d"),
+            String::from("bla/bla_enc"),
+            String::from("-k"),
+            String::from("suprsecret"),
+            String::from("--force"),
+            String::from("--force"),
+        ];
+        let parsed = parse_args::parse_args(args);
+        match parsed {
+            Err(ArgErr::ArgMismatch(_)) => Ok(()),
+            _ => Err(format!(
+                "should preduce ArgErr::ArgMismatch, but preduced {:?}",
+                parsed
+            )),
+        }
+    }
+
+    #[test]
+    fn shred_with_decrypt() -> Result<(), String> {
+        let args: Vec<String> = vec![
+            String::from("path/to/exctuable/"),
+            String::from("-d"),
+            String::from("bla/bla_enc"),
+            String::from("-k"),
+            String::from("suprsecret"),
+            String::from("--shred"),
+        ];
+        let parsed = parse_args::parse_args(args);
+        match parsed {
+            Err(ArgErr::ArgMismatch(_)) => Ok(()),
+            _ => Err(format!(
+                "should preduce ArgErr::ArgMismatch, but preduced {:?}",
+                parsed
+            )),
+        }
+    }
+
+    #[test]
+    fn stdin_stdout() {
+        assert!(args(&["-e", "-", "-k", "pw", "-o", "out_enc"]).is_ok());
+        assert!(args(&["-d", "-", "-k", "pw", "-o", "-"]).is_ok());
+        assert!(args(&["-e", "file", "-k", "pw", "-o", "-"]).is_ok());
+        // the header of stdin can only be rewritten in a file
+        assert!(matches!(
+            args(&["-e", "-", "-k", "pw", "-o", "-"]),
+            Err(ArgErr::ArgMismatch(_))
+        ));
+        // stdin has no name to derive an output from
+        assert!(matches!(
+            args(&["-d", "-", "-k", "pw"]),
+            Err(ArgErr::ArgMismatch(_))
+        ));
+        assert!(matches!(
+            args(&["-e", "-", "-k", "pw", "-o", "out_enc", "--shred"]),
+            Err(ArgErr::ArgMismatch(_))
+        ));
+        assert!(matches!(
+            args(&["-d", "-", "-k", "pw", "-o", "-", "-tail"]),
+            Err(ArgErr::ArgMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn commands() {
+        use parse_args::{Args, Command};
+        assert!(matches!(
+            args(&["encrypt", "--key=pw", "bla", "--block-size", "30"]).unwrap()[..],
+            [Args::Encrypt(_), Args::Key(_), Args::BlockSize(30)]
+        ));
+        assert!(matches!(
+            args(&["cat", "--tail", "-n", "3", "bla_enc"]).unwrap()[..],
+            [Args::Cat(_), Args::Tail, Args::Count(3)]
+        ));
+        // "--" ends the options, so expressions can start with "-"
+        match &args(&["grep", "-k", "pw", "--", "-x", "bla_enc"]).unwrap()[..] {
+            [Args::Grep(exp, path), Args::Key(_)] => {
+                assert_eq!((&exp[..], &path[..]), ("-x", "bla_enc"))
+            }
+            parsed => panic!("should parse grep, but preduced {:?}", parsed),
+        }
+        assert!(matches!(
+            args(&["decrypt", "bla", "--help"]).unwrap()[..],
+            [Args::Help(Some(Command::Decrypt))]
+        ));
+        assert!(matches!(args(&["--version"]).unwrap()[..], [Args::Version]));
+        assert!(matches!(args(&[]), Err(ArgErr::MissingArg(_))));
+        assert!(matches!(args(&["encrypt"]), Err(ArgErr::MissingArg(_))));
+        assert!(matches!(
+            args(&["encrypt", "-k"]),
+            Err(ArgErr::MissingArg(_))
+        ));
+        assert!(matches!(
+            args(&["encrypt", "a", "-o", "-k", "pw"]),
+            Err(ArgErr::MissingArg(_))
+        ));
+        assert!(matches!(
+            args(&["encrypt", "a", "--output=-a_enc", "-k", "pw"]).unwrap()[..],
+            [Args::Encrypt(_), Args::Output(ref o), Args::Key(_)] if o == "-a_enc"
+        ));
+        assert!(matches!(
+            args(&["shred", "bla"]),
+            Err(ArgErr::UnknownArg(_))
+        ));
+        assert!(matches!(
+            args(&["encrypt", "a", "b"]),
+            Err(ArgErr::UnknownArg(_))
+        ));
+        assert!(matches!(
+            args(&["encrypt", "a", "--bogus"]),
+            Err(ArgErr::UnknownArg(_))
+        ));
+        assert!(matches!(
+            args(&["encrypt", "a", "--force=yes"]),
+            Err(ArgErr::InvalidValue(_))
+        ));
+        // options of other commands
+        assert!(matches!(
+            args(&["decrypt", "a", "-b", "30"]),
+            Err(ArgErr::ArgMismatch(_))
+        ));
+        assert!(matches!(
+            args(&["info", "a", "-k", "pw"]),
+            Err(ArgErr::ArgMismatch(_))
+        ));
+        assert!(matches!(
+            args(&["encrypt", "-e", "a"]),
+            Err(ArgErr::ArgMismatch(_))
+        ));
+        assert!(matches!(
+            args(&["grep", "x", "-", "-k", "pw"]),
+            Err(ArgErr::ArgMismatch(_))
+        ));
+        assert!(matches!(
+            args(&["decrypt", "a", "--keep-going"]).unwrap()[..],
+            [Args::Decrypt(_), Args::KeepGoing]
+        ));
+        assert!(matches!(
+            args(&["cat", "a", "--keep-going"]),
+            Err(ArgErr::ArgMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn missing_key_value() -> Result<(), String> {
+        let args: Vec<String> = vec![
+            String::from("path/to/exctuable/"),
+            String::from("-e"),
+            String::from("bla/bla"),
+            String::from("-k"),
+        ];
+        let parsed = parse_args::parse_args(args);
+        match parsed {
+            Err(ArgErr::MissingArg(_)) => Ok(()),
+            _ => Err(format!(
+                "should preduce ArgErr::MissingArg, but preduced {:?}",
+                parsed
+            )),
+        }
+    }
 }
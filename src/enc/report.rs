@@ -0,0 +1,97 @@
+/// The exit status when every file failed, or brenc failed before getting to the files.
+pub const EXIT_FAILURE: i32 = 1;
+/// The exit status when some of the files were done and others failed.
+pub const EXIT_PARTIAL: i32 = 3;
+/// The exit status after ctrl-c, as a shell reports a process killed by SIGINT.
+pub const EXIT_CANCELLED: i32 = 130;
+
+/// A file of a batch that failed, and why.
+#[derive(Debug)]
+pub struct Failure {
+    pub path: String,
+    pub cause: String,
+}
+
+/// What happened to the files of a batch run with --keep-going: how many were done, and the
+/// files that failed.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub done: usize,
+    pub failures: Vec<Failure>,
+}
+
+impl Report {
+    /// Records a file that was done.
+    pub fn ok(&mut self) {
+        self.done += 1;
+    }
+
+    /// Records a file that failed, with the cause of the failure.
+    pub fn fail(&mut self, path: &str, cause: impl ToString) {
+        self.failures.push(Failure {
+            path: String::from(path),
+            cause: cause.to_string(),
+        });
+    }
+
+    /// The exit status for the batch: 0 if every file was done, EXIT_PARTIAL if only some of them
+    /// were, and EXIT_FAILURE if none was.
+    pub fn exit_code(&self) -> i32 {
+        match (self.done, self.failures.len()) {
+            (_, 0) => 0,
+            (0, _) => EXIT_FAILURE,
+            _ => EXIT_PARTIAL,
+        }
+    }
+
+    /// The summary of the batch: a table of the files that failed and their causes (with the
+    /// file column as wide as the longest path), followed by the totals.
+    pub fn format_summary(&self, action: &str) -> String {
+        let total = self.done + self.failures.len();
+        let mut res = String::new();
+        if !self.failures.is_empty() {
+            let width = self
+                .failures
+                .iter()
+                .map(|f| f.path.chars().count())
+                .chain(std::iter::once("FILE".len()))
+                .max()
+                .unwrap_or(0);
+            res.push_str(&format!("{:<width$}  CAUSE\n", "FILE", width = width));
+            for f in self.failures.iter() {
+                res.push_str(&format!("{:<width$}  {}\n", f.path, f.cause, width = width));
+            }
+        }
+        res.push_str(&format!(
+            "{} of {} files {}, {} failed",
+            self.done,
+            total,
+            action,
+            self.failures.len()
+        ));
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary() {
+        let mut report = Report::default();
+        assert_eq!(report.exit_code(), 0);
+        report.fail("a", "no such file");
+        assert_eq!(report.exit_code(), EXIT_FAILURE);
+        report.ok();
+        report.fail("dir/long_name", "permission denied");
+        assert_eq!(report.exit_code(), EXIT_PARTIAL);
+        assert_eq!(
+            report.format_summary("encrypted"),
+            "FILE           CAUSE
+a              no such file
+dir/long_name  permission denied
+1 of 3 files encrypted, 2 failed"
+        );
+    }
+}
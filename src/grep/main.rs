@@ -1,62 +1,66 @@
 #![warn(missing_debug_implementations, missing_docs)]
+//! brgrep: grep over files encrypted with brenc, without writing the clear text to disk.
 use common::*;
-use grep::{printer, regex, searcher};
-use printer::Standard;
-use regex::RegexMatcher;
-use searcher::Searcher;
+use glob::MatchOptions;
 use std::env::args;
 use std::process::exit;
-use termcolor::{ColorChoice, StandardStream};
 mod parse_args;
 use parse_args::Args;
 
-//use glob::MatchOptions;
-
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let parsed_args_res = parse_args::parse_args(args().collect());
     let parsed_args = match parsed_args_res {
         Ok(p) => p,
-        Err(_) => exit(0),
+        Err(e) => {
+            eprintln!("brgrep: {}", e);
+            eprintln!("see brgrep --help for the options");
+            exit(2);
+        }
     };
 
-    let mut key = String::new();
+    let mut key_source = password::KeySource::default();
     let mut file_path = String::new();
     let mut exp = String::new();
-    let mut head: bool = false;
-    let mut tail: bool = false;
+    let mut head_tail: Option<bool> = None;
+    let mut count: u64 = defaults::HEAD_TAIL_BLOCKS;
+    let mut execution = exec::Execution::default();
 
     for arg in parsed_args.into_iter() {
         match arg {
-            Args::Key(v) => key = v,
+            Args::Help => {
+                println!("{}", parse_args::usage());
+                return Ok(());
+            }
+            Args::Version => {
+                println!("brgrep {}", env!("CARGO_PKG_VERSION"));
+                return Ok(());
+            }
+            Args::Key(v) => key_source = v,
             Args::File(v) => file_path = v,
             Args::Exp(v) => exp = v,
-            Args::Head => head = true,
-            Args::Tail => tail = true,
+            Args::Head => head_tail = Some(true),
+            Args::Tail => head_tail = Some(false),
+            Args::Count(v) => count = v,
+            Args::Threads(v) => execution = exec::Execution::with_threads(exec::Strategy::Auto, v)?,
         }
     }
 
-    let f: counter_block::Blocks;
-    let mut block_num: i64 = 0;
-    if head {
-        f = file_mng::read_first_n(&file_path, 30)?; //TODO: set number of bytes
-    } else if tail {
-        let res = file_mng::read_last_n(&file_path, 30)?; //TODO: set number of bytes
-        f = res.0;
-        block_num = res.1;
-    } else {
-        f = file_mng::read_enc_file(&file_path)?;
+    let key = key_source.read(false)?;
+    let paths = file_mng::list_glob(&file_path, MatchOptions::new())?;
+    if paths.is_empty() {
+        eprintln!("no files match {}", file_path);
+        exit(1);
     }
-    let pass = key.into_bytes();
-    let dec_bytes = counter_block::par_decrypt(f, pass, block_num)?;
-    regex_grep(&dec_bytes, &exp)?;
-    Ok(())
-}
-
-/// Preform a regex grep over a byte array represanting a slice of clear text.  
-pub fn regex_grep(bytes: &[u8], exp: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let wrt = StandardStream::stdout(ColorChoice::Always);
-    let mut printer = Standard::new(wrt);
-    let matcher = RegexMatcher::new(exp)?;
-    Searcher::new().search_slice(&matcher, bytes, printer.sink(&matcher))?;
-    Ok(())
+    let bar = progress::Bar::unless_stdout_is_terminal(&paths);
+    let res = search::grep_files(
+        &paths,
+        &key,
+        &exp,
+        head_tail,
+        count,
+        &execution,
+        &|p, bytes, _| bar.advance(p, bytes),
+    );
+    bar.finish();
+    res
 }
@@ -1,166 +1,211 @@
+use common::defaults;
 use common::error::ArgErr;
+use common::password::KeySource;
+use zeroize::Zeroizing;
 
 #[derive(Debug)]
 pub enum Args {
-    Key(String),
+    /// print the help and exit.
+    Help,
+    /// print the version and exit.
+    Version,
+    Key(KeySource),
     File(String),
     Exp(String),
     Head,
     Tail,
+    Count(u64),
+    Threads(usize),
 }
 
 /// Parsing the arguments for brgrep utility.
 /// returns Vec<Args> of arguments to be handled by the main program
 ///
+/// options have a long form ("--file <path>" or "--file=<path>") and most have a short one ("-f
+/// <path>"). the expression is the only argument that is not an option, and "--" ends the
+/// options, for expressions that start with "-".
+///
 /// # Examples
-/// ```rust
-/// use grep::{parse_args, Args};
-/// let args: Vec<String> = vec![String::from("-f"),
+/// ```ignore
+/// use crate::parse_args::{parse_args, Args};
+/// use common::password::KeySource;
+/// let args: Vec<String> = vec![String::from("brgrep"),
+///                             String::from("-f"),
 ///                             String::from("/home/user/test.txt"),
-///                             String::from("-k")
-///                             String::from("secretkey")
-///                             String::from("-head"),
+///                             String::from("-k"),
+///                             String::from("secretkey"),
+///                             String::from("--head"),
 ///                             String::from("/w+ hello /d")];
 /// let res = parse_args(args).unwrap();
-/// assert_eq!(res, vec![Args::Key(String::from("secretkey")),
-///                     Args::File(String::from("/home/user/test.txt")),
-///                     Args::Head,
-///                     Args::Exp(String::from("/w+ hello /d"))]);
+/// assert!(matches!(&res[..],
+///                  [Args::Exp(exp), Args::File(file), Args::Key(KeySource::Given(_)), Args::Head]
+///                      if exp == "/w+ hello /d" && file == "/home/user/test.txt"));
 /// ```
-pub fn parse_args(mut argv: Vec<String>) -> Result<Vec<Args>, ArgErr> {
-    //TODO: implement input validation from other parse_args
-
+///
+/// # Errors
+/// returns an ArgErr telling what is wrong if an argument is missing, given twice, unknown,
+/// conflicts with another one or has a value out of range.
+pub fn parse_args(argv: Vec<String>) -> Result<Vec<Args>, ArgErr> {
+    // a key given with -k is one of the arguments, so all of them are wiped once parsed
+    let mut argv = Zeroizing::new(argv);
     argv.remove(0);
     let mut final_args: Vec<Args> = Vec::new();
-
-    let exp = argv.pop();
-    match exp {
-        Some(v) => final_args.push(Args::Exp(v)),
-        None => {
-            print_usege();
-            return Err(ArgErr::MissingArg);
+    let mut exps: Vec<String> = Vec::new();
+    let mut index = 0;
+    while index < argv.len() {
+        let arg = &argv[index];
+        index += 1;
+        if arg == "--" {
+            exps.extend(argv[index..].iter().cloned());
+            break;
         }
-    }
-
-    let argv_iter = argv.iter().enumerate();
-    let mut is_param: bool = false;
-    for (index, arg) in argv_iter {
-        if is_param {
-            is_param = false;
+        if !arg.starts_with('-') || arg == "-" {
+            exps.push(arg.clone());
             continue;
         }
-        match &arg[..] {
-            "-k" => {
-                final_args.push(Args::Key(String::from(&argv[index + 1])));
-                is_param = true;
+        let (flag, mut inline) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => (&arg[..i], Some(String::from(&arg[i + 1..]))),
+            _ => (&arg[..], None),
+        };
+        let mut value = || -> Result<String, ArgErr> {
+            if let Some(v) = inline.take() {
+                return Ok(v);
             }
-            "-f" => {
-                final_args.push(Args::File(String::from(&argv[index + 1])));
-                is_param = true;
+            // an option is never taken as the value of the one before it, values starting with '-'
+            // are given as --flag=value
+            match argv.get(index) {
+                Some(v) if v == "-" || !v.starts_with('-') => {
+                    index += 1;
+                    Ok(v.clone())
+                }
+                _ => Err(ArgErr::MissingArg(format!("the value of {}", flag))),
             }
-            "-head" => {
-                final_args.push(Args::Head);
-            }
-            "-tail" => {
-                final_args.push(Args::Tail);
-            }
-
-            _ => {
-                print_usege();
-                return Err(ArgErr::UnknownArg(String::from(arg)));
+        };
+        let parsed = match flag {
+            "-h" | "--help" => return Ok(vec![Args::Help]),
+            "-V" | "--version" => return Ok(vec![Args::Version]),
+            "-k" | "--key" | "--key-env" | "--key-file" | "--key-fd" => {
+                Args::Key(KeySource::parse(flag, value()?)?)
             }
+            "-f" | "--file" => Args::File(value()?),
+            "-n" | "--count" => Args::Count(parse_count(&value()?)?),
+            "--threads" => Args::Threads(parse_threads(&value()?)?),
+            "-head" | "--head" => Args::Head,
+            "-tail" | "--tail" => Args::Tail,
+            _ => return Err(ArgErr::UnknownArg(String::from(arg))),
+        };
+        if inline.is_some() {
+            return Err(ArgErr::InvalidValue(format!("{} takes no value", flag)));
+        }
+        final_args.push(parsed);
+    }
+    match exps.len() {
+        0 => {
+            return Err(ArgErr::MissingArg(String::from(
+                "the expression to search for",
+            )))
+        }
+        1 => final_args.insert(0, Args::Exp(exps.remove(0))),
+        _ => {
+            return Err(ArgErr::UnknownArg(format!(
+                "{} (give a single expression, and the files with --file)",
+                exps[0]
+            )))
         }
     }
 
-    //    let mut flags: Vec<(usize, &str)> = Vec::new();
-    //    for (index, arg) in argv.iter().enumerate() {
-    //        if let Some('-') = arg.chars().next() {
-    //            flags.push((index, arg))
-    //        }
-    //    }
-    //
-    //    let mut used_flags: Vec<&str> = Vec::new();
-    //    for (index, flag) in flags.into_iter() {
-    //        if used_flags.iter().any(|a| a == &flag) {
-    //            print_usege();
-    //            return Err(());
-    //        }
-    //
-    //        match flag {
-    //            "-k" => {
-    //                let val = argv.get(index + 1).ok_or(())?;
-    //                final_args.push(Args::Key(String::from(val)));
-    //                used_flags.push(flag)
-    //            }
-    //            "-f" => {
-    //                let val = argv.get(index + 1).ok_or(())?;
-    //                final_args.push(Args::File(String::from(val)));
-    //                used_flags.push(flag)
-    //            }
-    //            "-head" => {
-    //                final_args.push(Args::Head);
-    //                used_flags.push(flag)
-    //            }
-    //            "-tail" => {
-    //                final_args.push(Args::Tail);
-    //                used_flags.push(flag)
-    //            }
-    //
-    //            _ => {
-    //                print_usege();
-    //                return Err(());
-    //            }
-    //        }
-    //    }
-    //    let exp = argv.pop();
-    //    match exp {
-    //        Some(v) => final_args.push(Args::Exp(v)),
-    //        None => {
-    //            print_usege();
-    //            return Err(());
-    //        }
-    //    }
     validate_input(&final_args)?;
     Ok(final_args)
 }
 
+/// parses the number of blocks for --head / --tail, which has to be positive.
+fn parse_count(v: &str) -> Result<u64, ArgErr> {
+    match v.parse::<u64>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(ArgErr::InvalidValue(format!(
+            "number of blocks should be positive, got {}",
+            v
+        ))),
+    }
+}
+
+/// parses the number of threads, which has to be positive.
+fn parse_threads(v: &str) -> Result<usize, ArgErr> {
+    match v.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(ArgErr::InvalidValue(format!(
+            "number of threads should be positive, got {}",
+            v
+        ))),
+    }
+}
+
 fn validate_input(v: &[Args]) -> Result<(), ArgErr> {
     let mut file: u8 = 0;
-    let mut exp: u8 = 0;
     let mut key: u8 = 0;
     let mut head: u8 = 0;
     let mut tail: u8 = 0;
+    let mut count: u8 = 0;
+    let mut threads: u8 = 0;
 
     for arg in v.iter() {
         match arg {
-            Args::Exp(_) => exp += 1,
             Args::File(_) => file += 1,
             Args::Key(_) => key += 1,
             Args::Head => head += 1,
             Args::Tail => tail += 1,
+            Args::Count(_) => count += 1,
+            Args::Threads(_) => threads += 1,
+            Args::Exp(_) | Args::Help | Args::Version => {}
         }
     }
-    if file != 1 {
-        print_usege();
-        return Err(ArgErr::MissingArg);
-    } else if key != 1 {
-        print_usege();
-        return Err(ArgErr::MissingArg);
-    } else if exp != 1 {
-        print_usege();
-        return Err(ArgErr::MissingArg);
+    let mismatch = |msg: &str| Err(ArgErr::ArgMismatch(String::from(msg)));
+    if file == 0 {
+        Err(ArgErr::MissingArg(String::from(
+            "the files to search, given with --file",
+        )))
+    } else if file > 1 {
+        mismatch("--file is given more then once (quote globs so the shell does not expand them)")
+    } else if key > 1 {
+        mismatch("give only one of --key, --key-env, --key-file and --key-fd")
     } else if (head + tail) > 1 {
-        print_usege();
-        return Err(ArgErr::ArgMismatch);
+        mismatch("--head and --tail can not be used together")
+    } else if count > 1 {
+        mismatch("--count is given more then once")
+    } else if threads > 1 {
+        mismatch("--threads is given more then once")
+    } else if count == 1 && (head + tail) == 0 {
+        mismatch("--count needs --head or --tail")
+    } else {
+        Ok(())
     }
-    Ok(())
 }
 
-fn print_usege() {
-    println!(
-        "usege: 
-        brgrep -f <path/to/file/or/dir> -k <key> [-head / -tail] <expretion>"
-    );
+/// the help of brgrep.
+pub fn usage() -> String {
+    format!(
+        "brgrep: search files encrypted with brenc, without writing their clear text to disk
+
+usage: brgrep -f <path> [options] <expression>
+
+the path is a file or a glob of them (quoted, so the shell does not expand it). the expression is
+a regular expression, put it after -- if it starts with -.
+
+key (prompted for, without echo, if none is given):
+    -k, --key <key>             the key itself (it is left in shell history and ps)
+    --key-env <var>             read the key from an environment variable
+    --key-file <path>           read the key from a file (a trailing newline is dropped)
+    --key-fd <n>                read the key from the first line of an open file descriptor
+
+options:
+    -f, --file <path>           the files to search
+    --head / --tail             only search the first / last blocks of every file
+    -n, --count <blocks>        number of blocks for --head / --tail (default {})
+    --threads <n>               number of threads to use (default: one per cpu)
+    -h, --help                  print this help
+    -V, --version               print the version",
+        defaults::HEAD_TAIL_BLOCKS
+    )
 }
 
 #[cfg(test)]
@@ -178,9 +223,9 @@ mod tests {
         ];
         let parsed = parse_args::parse_args(args);
         match parsed {
-            Err(ArgErr::UnknownArg(_)) => Ok(()),
+            Err(ArgErr::MissingArg(_)) => Ok(()),
             _ => Err(format!(
-                "should preduce ArgErr::UnknownArg, but preduced {:?}",
+                "should preduce ArgErr::MissingArg, but preduced {:?}",
                 parsed
             )),
         }
@@ -200,7 +245,7 @@ mod tests {
         ];
         let parsed = parse_args::parse_args(args);
         match parsed {
-            Err(ArgErr::ArgMismatch) => Ok(()),
+            Err(ArgErr::ArgMismatch(_)) => Ok(()),
             _ => Err(format!(
                 "should preduce ArgErr::ArgMismatch, but preduced {:?}",
                 parsed
@@ -222,11 +267,61 @@ mod tests {
         ];
         let parsed = parse_args::parse_args(args);
         match parsed {
-            Err(ArgErr::MissingArg) => Ok(()),
+            Err(ArgErr::ArgMismatch(_)) => Ok(()),
             _ => Err(format!(
                 "should preduce ArgErr::ArgMismatch, but preduced {:?}",
                 parsed
             )),
         }
     }
+
+    #[test]
+    fn two_key_sources() -> Result<(), String> {
+        let args: Vec<String> = vec![
+            String::from("path/to/exctuable/"),
+            String::from("-f"),
+            String::from("bla/bla"),
+            String::from("-k"),
+            String::from("suprsecret"),
+            String::from("--key-file"),
+            String::from("bla/key"),
+            String::from(r"exp\w*"),
+        ];
+        let parsed = parse_args::parse_args(args);
+        match parsed {
+            Err(ArgErr::ArgMismatch(_)) => Ok(()),
+            _ => Err(format!(
+                "should preduce ArgErr::ArgMismatch, but preduced {:?}",
+                parsed
+            )),
+        }
+    }
+
+    #[test]
+    fn long_options() {
+        let args = |v: &[&str]| {
+            let mut argv = vec![String::from("path/to/exctuable/")];
+            argv.extend(v.iter().map(|s| String::from(*s)));
+            parse_args::parse_args(argv)
+        };
+        match &args(&["--file=bla/*_enc", "--key", "pw", "--tail", "--", "-x"]).unwrap()[..] {
+            [parse_args::Args::Exp(e), parse_args::Args::File(f), parse_args::Args::Key(_), parse_args::Args::Tail] =>
+            {
+                assert_eq!((&e[..], &f[..]), ("-x", "bla/*_enc"))
+            }
+            parsed => panic!("should parse the long options, but preduced {:?}", parsed),
+        }
+        assert!(matches!(
+            args(&["-f", "bla", "exp", "--help"]).unwrap()[..],
+            [parse_args::Args::Help]
+        ));
+        assert!(matches!(
+            args(&["-f", "bla", "-k"]),
+            Err(ArgErr::MissingArg(_))
+        ));
+        assert!(matches!(
+            args(&["-k", "pw", "exp"]),
+            Err(ArgErr::MissingArg(_))
+        ));
+    }
 }